@@ -1,8 +1,9 @@
 
 extern crate rustc_version;
 use rustc_version::{version, version_meta, Channel};
+use std::env;
 
-fn build_tramp()
+fn build_tramp(unwind: bool)
 {
     let mut builder = cc::Build::new();
     // --std=c99 -W -Wall -Werror -pedantic -O3 -flto
@@ -12,8 +13,12 @@ fn build_tramp()
 	.flag_if_supported("-Wextra")
 	.flag("-Werror")
 	.flag("-pedantic")
-	.opt_level(3)
-
+	.opt_level(3);
+    if unwind {
+	// Panics unwind through the trampoline, so it needs unwind tables.
+	builder.flag("-fexceptions");
+    }
+    builder
  	// Not sure if we want these two. We can check the codegen later.
 	// .pic(false)
 	// .use_plt(false)
@@ -22,12 +27,40 @@ fn build_tramp()
 	.compile("calloca_trampoline");
 }
 
+/// Should the trampoline be implemented in assembly instead of C?
+///
+/// Only enabled by the `asm_trampoline` feature on supported targets, otherwise we fall back to the C trampoline.
+fn use_asm_tramp() -> bool
+{
+    let arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap_or_default();
+    let os = env::var("CARGO_CFG_TARGET_OS").unwrap_or_default();
+    // Windows needs shadow space above the return address, which would overlap the buffer.
+    env::var_os("CARGO_FEATURE_ASM_TRAMPOLINE").is_some()
+	&& (arch == "x86_64" || arch == "aarch64")
+	&& os != "windows"
+}
+
+/// Should panics unwind through the trampoline?
+///
+/// Only with `no_std` (where panics cannot be caught) on nightly toolchains, unless we know panics abort. Elsewhere `no_std` requires `panic = "abort"` (see `lib.rs`.)
+fn use_unwind_tramp(nightly: bool) -> bool
+{
+    nightly
+	&& env::var_os("CARGO_FEATURE_NO_STD").is_some()
+	&& env::var("CARGO_CFG_PANIC").map_or(true, |panic| panic != "abort")
+}
+
 fn main() {
+    // Declare the cfgs this script sets below.
+    for cfg in &["stable", "beta", "nightly", "dev", "asm_trampoline", "unwind_trampoline"] {
+	println!("cargo:rustc-check-cfg=cfg({})", cfg);
+    }
     // Assert we haven't travelled back in time
     assert!(version().unwrap().major >= 1);
 
     // Set cfg flags depending on release channel
-    match version_meta().unwrap().channel {
+    let channel = version_meta().unwrap().channel;
+    match channel {
         Channel::Stable => {
             println!("cargo:rustc-cfg=stable");
         }
@@ -42,5 +75,14 @@ fn main() {
         }
     }
 
-    build_tramp();
+    let unwind = use_unwind_tramp(channel == Channel::Nightly);
+    if unwind {
+	println!("cargo:rustc-cfg=unwind_trampoline");
+    }
+
+    if use_asm_tramp() {
+	println!("cargo:rustc-cfg=asm_trampoline");
+    } else {
+	build_tramp(unwind);
+    }
 }
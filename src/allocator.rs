@@ -0,0 +1,153 @@
+//! An `Allocator` over a single stack allocation, for collections whose storage should live on the stack.
+//!
+//! `StackAllocator` implements the unstable `core::alloc::Allocator` trait with the `allocator_api` feature on nightly toolchains, and `allocator_api2::alloc::Allocator` with the `allocator-api2` feature.
+//! It is a bump allocator: memory is only reclaimed when the most recent allocation is freed (or resized), and all of it is released when `with_stack_allocator()` returns.
+//!
+//! # Example
+//! ```
+//! # #[cfg(feature = "allocator-api2")] {
+//! # use stackalloc::with_stack_allocator;
+//! use allocator_api2::vec::Vec;
+//!
+//! let sum = with_stack_allocator(1024, |alloc| {
+//!   let mut vec = Vec::new_in(alloc);
+//!   vec.extend(1..=10u64);
+//!   vec.iter().sum::<u64>()
+//! });
+//! assert_eq!(sum, 55);
+//! # }
+//! ```
+use super::*;
+use super::bump::Bump;
+use core::{
+    fmt,
+    marker::PhantomData,
+};
+
+/// A bump allocator over a stack allocated buffer, created by `with_stack_allocator()`.
+///
+/// Allocations that do not fit in the remaining space of the buffer fail.
+pub struct StackAllocator<'a>
+{
+    bump: Bump,
+    _marker: PhantomData<&'a mut [MaybeUninit<u8>]>,
+}
+
+impl<'a> StackAllocator<'a>
+{
+    /// The number of bytes left in the buffer.
+    #[inline] pub fn remaining(&self) -> usize
+    {
+	self.bump.remaining()
+    }
+
+    /// The number of bytes currently allocated from the buffer, including alignment padding.
+    #[inline] pub fn used(&self) -> usize
+    {
+	self.bump.used()
+    }
+
+    /// Allocate a block for `layout`.
+    #[inline] fn allocate_block(&self, layout: Layout) -> Option<ptr::NonNull<[u8]>>
+    {
+	self.bump.alloc(layout).map(|ptr| ptr::NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    /// Free the block at `ptr`, which is only reclaimed if it was the most recent allocation.
+    ///
+    /// # Safety
+    /// `ptr` must be a block of `layout` allocated by this allocator.
+    #[inline] unsafe fn deallocate_block(&self, ptr: ptr::NonNull<u8>, layout: Layout)
+    {
+	if self.bump.is_last(ptr.as_ptr(), layout.size()) {
+	    self.bump.resize_last(ptr.as_ptr(), 0);
+	}
+    }
+
+    /// Resize the block at `ptr` from `old` to `new`, in place if it is the most recent allocation, or by moving it otherwise.
+    ///
+    /// # Safety
+    /// `ptr` must be a block of `old` allocated by this allocator.
+    unsafe fn resize_block(&self, ptr: ptr::NonNull<u8>, old: Layout, new: Layout) -> Option<ptr::NonNull<[u8]>>
+    {
+	if align_padding(ptr.as_ptr(), new.align()) == 0 {
+	    if self.bump.is_last(ptr.as_ptr(), old.size()) {
+		if self.bump.resize_last(ptr.as_ptr(), new.size()) {
+		    return Some(ptr::NonNull::slice_from_raw_parts(ptr, new.size()));
+		}
+	    } else if new.size() <= old.size() {
+		// The rest of the block is wasted until the buffer is released.
+		return Some(ptr::NonNull::slice_from_raw_parts(ptr, new.size()));
+	    }
+	}
+
+	let block = self.allocate_block(new)?;
+	ptr::copy_nonoverlapping(ptr.as_ptr(), block.as_ptr() as *mut u8, old.size().min(new.size()));
+	self.deallocate_block(ptr, old);
+	Some(block)
+    }
+}
+
+impl<'a> fmt::Debug for StackAllocator<'a>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+	f.debug_struct("StackAllocator")
+	    .field("used", &self.used())
+	    .field("remaining", &self.remaining())
+	    .finish()
+    }
+}
+
+/// Implement an `Allocator` trait with the same interface as `core::alloc::Allocator`.
+#[cfg(any(all(nightly, feature = "allocator_api"), feature = "allocator-api2"))]
+macro_rules! impl_allocator {
+    ($($allocator:ident)::+, $($error:ident)::+) => {
+	unsafe impl<'a> $($allocator)::+ for StackAllocator<'a>
+	{
+	    #[inline] fn allocate(&self, layout: Layout) -> Result<ptr::NonNull<[u8]>, $($error)::+>
+	    {
+		self.allocate_block(layout).ok_or($($error)::+)
+	    }
+
+	    #[inline] unsafe fn deallocate(&self, ptr: ptr::NonNull<u8>, layout: Layout)
+	    {
+		self.deallocate_block(ptr, layout)
+	    }
+
+	    #[inline] unsafe fn grow(&self, ptr: ptr::NonNull<u8>, old_layout: Layout, new_layout: Layout) -> Result<ptr::NonNull<[u8]>, $($error)::+>
+	    {
+		self.resize_block(ptr, old_layout, new_layout).ok_or($($error)::+)
+	    }
+
+	    #[inline] unsafe fn shrink(&self, ptr: ptr::NonNull<u8>, old_layout: Layout, new_layout: Layout) -> Result<ptr::NonNull<[u8]>, $($error)::+>
+	    {
+		self.resize_block(ptr, old_layout, new_layout).ok_or($($error)::+)
+	    }
+	}
+    };
+}
+
+#[cfg(all(nightly, feature = "allocator_api"))]
+impl_allocator!(core::alloc::Allocator, core::alloc::AllocError);
+
+#[cfg(feature = "allocator-api2")]
+impl_allocator!(allocator_api2::alloc::Allocator, allocator_api2::alloc::AllocError);
+
+/// Allocate a runtime length buffer of `size` bytes on the stack, call `callback` with a `StackAllocator` over this buffer, and then deallocate the buffer.
+///
+/// Collections using the allocator cannot outlive `callback`, so they are always dropped before the buffer is deallocated.
+///
+/// See `alloca()`.
+#[inline] pub fn with_stack_allocator<U, F>(size: usize, callback: F) -> U
+where F: FnOnce(&StackAllocator<'_>) -> U
+{
+    alloca(size, move |buf| {
+	let allocator = StackAllocator {
+	    // SAFETY: `buf` lives until the allocator is dropped.
+	    bump: unsafe { Bump::new(buf.as_mut_ptr() as *mut u8, buf.len()) },
+	    _marker: PhantomData,
+	};
+	callback(&allocator)
+    })
+}
@@ -0,0 +1,269 @@
+//! A bump arena over a single stack allocation, for making many small allocations of different types.
+//!
+//! # Example
+//! ```
+//! # use stackalloc::with_stack_arena;
+//! let total = with_stack_arena(1024, |arena| {
+//!   let name = arena.alloc_str("request");
+//!   let ids = arena.alloc_slice_copy(&[1u32, 2, 3]);
+//!   let owned = arena.alloc(vec![name.len(); 2]); // Dropped when the closure returns
+//!   ids.iter().sum::<u32>() as usize + owned.iter().sum::<usize>()
+//! });
+//! assert_eq!(total, 20);
+//! ```
+use super::*;
+use super::bump::Bump;
+use core::{
+    cell::{
+	Cell,
+	RefCell,
+    },
+    fmt,
+    marker::PhantomData,
+};
+
+/// The smallest heap chunk a spilling `StackArena` allocates.
+const MIN_CHUNK_SIZE: usize = 4096;
+
+/// A node in the list of values to drop, allocated in the arena just before the value itself.
+struct DropEntry
+{
+    next: *mut DropEntry,
+    drop: unsafe fn(*mut DropEntry),
+}
+
+/// An arena allocation of a value that needs dropping.
+#[repr(C)]
+struct DropSlot<T>
+{
+    entry: DropEntry,
+    value: T,
+}
+
+/// Drop the value of the `DropSlot<T>` that starts with `entry`.
+unsafe fn drop_slot<T>(entry: *mut DropEntry)
+{
+    ptr::drop_in_place(&mut (*(entry as *mut DropSlot<T>)).value);
+}
+
+/// A bump arena allocator over a stack allocated buffer, created by `with_stack_arena()`.
+///
+/// Values allocated in the arena live until `with_stack_arena()` returns, when those that need dropping are dropped in the reverse order they were allocated.
+///
+/// By default, allocating more than fits in the buffer fails. If spilling is enabled with `set_spill(true)`, new chunks are instead allocated on the heap.
+pub struct StackArena<'a>
+{
+    bump: Bump,
+    drops: Cell<*mut DropEntry>,
+    spill: Cell<bool>,
+    chunks: RefCell<Vec<Box<[MaybeUninit<u8>]>>>,
+    // Invariant, so that values allocated in the arena cannot be shorter lived than the arena itself.
+    _marker: PhantomData<Cell<&'a ()>>,
+}
+
+/// Returned when a `StackArena` that does not spill to the heap has no space left for an allocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct ArenaExhausted;
+
+impl fmt::Display for ArenaExhausted
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+	write!(f, "stack arena exhausted")
+    }
+}
+
+impl std::error::Error for ArenaExhausted{}
+
+impl<'a> StackArena<'a>
+{
+    /// Set whether this arena allocates more memory on the heap when its buffer is exhausted, instead of failing.
+    #[inline] pub fn set_spill(&self, spill: bool)
+    {
+	self.spill.set(spill);
+    }
+
+    /// Will this arena allocate on the heap when its buffer is exhausted?
+    #[inline] pub fn spills(&self) -> bool
+    {
+	self.spill.get()
+    }
+
+    /// Has this arena allocated any memory on the heap?
+    #[inline] pub fn is_allocated(&self) -> bool
+    {
+	!self.chunks.borrow().is_empty()
+    }
+
+    /// The number of bytes left in the current buffer.
+    ///
+    /// This is the stack buffer, unless the arena has moved to the heap.
+    #[inline] pub fn remaining(&self) -> usize
+    {
+	self.bump.remaining()
+    }
+
+    /// Allocate a region for `layout` in the arena, moving to a new heap chunk if needed and allowed.
+    fn alloc_layout(&self, layout: Layout) -> Result<ptr::NonNull<u8>, ArenaExhausted>
+    {
+	if let Some(ptr) = self.bump.alloc(layout) {
+	    return Ok(ptr);
+	}
+	if !self.spill.get() {
+	    return Err(ArenaExhausted);
+	}
+
+	let mut chunks = self.chunks.borrow_mut();
+	let last = chunks.last().map(|chunk| chunk.len()).unwrap_or(MIN_CHUNK_SIZE / 2);
+	let size = padded_size(layout).max(last.saturating_mul(2));
+	let mut chunk: Box<[MaybeUninit<u8>]> = std::iter::repeat_with(MaybeUninit::uninit).take(size).collect();
+	// SAFETY: The chunk is kept alive (and not moved, since it is boxed) until the arena is dropped.
+	unsafe {
+	    self.bump.switch_to(chunk.as_mut_ptr() as *mut u8, chunk.len());
+	}
+	chunks.push(chunk);
+	Ok(self.bump.alloc(layout).expect("new chunk is large enough for the allocation"))
+    }
+
+    /// Move `value` into the arena and return a reference to it, or return `value` back if there is no space for it.
+    ///
+    /// `value` is dropped when the arena is, if it needs dropping.
+    #[allow(clippy::mut_from_ref)]
+    pub fn try_alloc<T: 'a>(&self, value: T) -> Result<&mut T, T>
+    {
+	if !mem::needs_drop::<T>() {
+	    return match self.alloc_layout(Layout::new::<T>()) {
+		// SAFETY: The region is aligned and valid for a `T`, and is not used by anything else.
+		Ok(ptr) => Ok(unsafe {
+		    let ptr = ptr.as_ptr() as *mut T;
+		    ptr.write(value);
+		    &mut *ptr
+		}),
+		Err(_) => Err(value),
+	    };
+	}
+
+	let slot = match self.alloc_layout(Layout::new::<DropSlot<T>>()) {
+	    Ok(ptr) => ptr.as_ptr() as *mut DropSlot<T>,
+	    Err(_) => return Err(value),
+	};
+	// SAFETY: The region is aligned and valid for a `DropSlot<T>`, and is not used by anything else.
+	// Once it is linked into `drops`, the value is dropped by `drop_slot::<T>()` when the arena is.
+	unsafe {
+	    slot.write(DropSlot {
+		entry: DropEntry {
+		    next: self.drops.get(),
+		    drop: drop_slot::<T>,
+		},
+		value,
+	    });
+	    self.drops.set(slot as *mut DropEntry);
+	    Ok(&mut (*slot).value)
+	}
+    }
+
+    /// Move `value` into the arena and return a reference to it.
+    ///
+    /// # Panics
+    /// If there is no space for `value` and spilling is disabled.
+    #[allow(clippy::mut_from_ref)]
+    #[inline] pub fn alloc<T: 'a>(&self, value: T) -> &mut T
+    {
+	match self.try_alloc(value) {
+	    Ok(value) => value,
+	    Err(_) => panic!("{}", ArenaExhausted),
+	}
+    }
+
+    /// Copy `slice` into the arena and return a reference to the copy, or return an error if there is no space for it.
+    #[allow(clippy::mut_from_ref)]
+    pub fn try_alloc_slice_copy<T: Copy>(&self, slice: &[T]) -> Result<&mut [T], ArenaExhausted>
+    {
+	// An existing slice always has a valid layout.
+	let ptr = self.alloc_layout(Layout::for_value(slice))?.as_ptr() as *mut T;
+	// SAFETY: The region is aligned and valid for `slice.len()` elements of `T`, and is not used by anything else.
+	Ok(unsafe {
+	    ptr::copy_nonoverlapping(slice.as_ptr(), ptr, slice.len());
+	    slice::from_raw_parts_mut(ptr, slice.len())
+	})
+    }
+
+    /// Copy `slice` into the arena and return a reference to the copy.
+    ///
+    /// # Panics
+    /// If there is no space for `slice` and spilling is disabled.
+    #[allow(clippy::mut_from_ref)]
+    #[inline] pub fn alloc_slice_copy<T: Copy>(&self, slice: &[T]) -> &mut [T]
+    {
+	self.try_alloc_slice_copy(slice).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Copy `string` into the arena and return a reference to the copy, or return an error if there is no space for it.
+    #[allow(clippy::mut_from_ref)]
+    #[inline] pub fn try_alloc_str(&self, string: &str) -> Result<&mut str, ArenaExhausted>
+    {
+	let bytes = self.try_alloc_slice_copy(string.as_bytes())?;
+	// SAFETY: The bytes were copied from a `str`.
+	Ok(unsafe { core::str::from_utf8_unchecked_mut(bytes) })
+    }
+
+    /// Copy `string` into the arena and return a reference to the copy.
+    ///
+    /// # Panics
+    /// If there is no space for `string` and spilling is disabled.
+    #[allow(clippy::mut_from_ref)]
+    #[inline] pub fn alloc_str(&self, string: &str) -> &mut str
+    {
+	self.try_alloc_str(string).unwrap_or_else(|err| panic!("{}", err))
+    }
+}
+
+impl<'a> Drop for StackArena<'a>
+{
+    fn drop(&mut self)
+    {
+	let mut entry = self.drops.replace(ptr::null_mut());
+	while !entry.is_null() {
+	    // SAFETY: Every entry in the list was created by `try_alloc()`, and each value is only dropped once since we unlink its entry first.
+	    unsafe {
+		let DropEntry { next, drop } = ptr::read(entry);
+		self.drops.set(next);
+		drop(entry);
+		entry = next;
+	    }
+	}
+    }
+}
+
+impl<'a> fmt::Debug for StackArena<'a>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+	f.debug_struct("StackArena")
+	    .field("remaining", &self.remaining())
+	    .field("spill", &self.spills())
+	    .field("heap_chunks", &self.chunks.borrow().len())
+	    .finish()
+    }
+}
+
+/// Allocate a runtime length buffer of `bytes` bytes on the stack, call `callback` with a `StackArena` over this buffer, and then drop every value allocated in it and deallocate the buffer.
+///
+/// Values are dropped in the reverse order they were allocated, when `callback` returns or panics.
+///
+/// See `alloca()`.
+pub fn with_stack_arena<'a, U, F>(bytes: usize, callback: F) -> U
+where F: FnOnce(&StackArena<'a>) -> U
+{
+    alloca(bytes, move |buf| {
+	let arena = StackArena {
+	    // SAFETY: `buf` lives until the arena is dropped, and references into it cannot escape `callback`.
+	    bump: unsafe { Bump::new(buf.as_mut_ptr() as *mut u8, buf.len()) },
+	    drops: Cell::new(ptr::null_mut()),
+	    spill: Cell::new(false),
+	    chunks: RefCell::new(Vec::new()),
+	    _marker: PhantomData,
+	};
+	callback(&arena)
+    })
+}
@@ -4,23 +4,34 @@ use std::mem::{
     ManuallyDrop,
 };
 use std::marker::{Send, Sync, PhantomData};
-use std::ops::Drop;
-use std::slice;
+use std::ops::{
+    Drop,
+    Deref, DerefMut,
+    Index, IndexMut,
+    Range, RangeBounds, Bound,
+};
+use std::slice::{
+    self,
+    SliceIndex,
+};
+use std::iter::FusedIterator;
+use std::{
+    ptr,
+    fmt,
+    vec,
+};
 
 #[repr(C)]
 #[derive(Debug)]
 struct StackBuffer<T>
 {
-    fill_ptr: usize, 
+    fill_ptr: usize,
     buf_ptr: *mut MaybeUninit<T>,
 }
 impl<T> Clone for StackBuffer<T>
 {
     fn clone(&self) -> Self {
-	Self{
-	    fill_ptr: self.fill_ptr,
-	    buf_ptr: self.buf_ptr,
-	}
+	*self
     }
 }
 impl<T> Copy for StackBuffer<T>{}
@@ -29,7 +40,7 @@ impl<T> Copy for StackBuffer<T>{}
 #[derive(Debug, Clone)]
 struct HeapBuffer<T>
 {
-    _fill_ptr: usize, // vec.len()
+    _fill_ptr: usize, // always `usize::MAX`, so that `is_allocated()` holds.
     buf: Vec<T>,
 }
 
@@ -44,13 +55,14 @@ union Internal<T>
 pub struct AVec<'a, T>
 {
     /// max size of `inner.stack` before it's moved to `inner.heap`.
-    stack_sz: usize, 
+    stack_sz: usize,
     inner: Internal<T>,
 
     _stack: PhantomData<&'a mut [MaybeUninit<T>]>,
 }
-unsafe impl<'a, T> Send for AVec<'a, T>{}
-unsafe impl<'a, T> Sync for AVec<'a, T>{}
+// Only as thread safe as the elements it owns.
+unsafe impl<'a, T: Send> Send for AVec<'a, T>{}
+unsafe impl<'a, T: Sync> Sync for AVec<'a, T>{}
 
 impl<'a, T> Drop for AVec<'a, T>
 {
@@ -60,17 +72,10 @@ impl<'a, T> Drop for AVec<'a, T>
 	    unsafe {
 		ManuallyDrop::drop(&mut self.inner.heap);
 	    }
-	} else {
-	    if std::mem::needs_drop::<T>() {
-		// Drop the allocated stack elements in place
-		unsafe {
-		    std::ptr::drop_in_place(std::ptr::slice_from_raw_parts_mut(self.inner.stack.buf_ptr as *mut T, self.fill_ptr())); // I think this drops the elements, we don't need to loop.
-		    /*
-		    for x in slice::from_raw_parts_mut(self.inner.stack.buf_ptr, self.fill_ptr())
-		    {
-		    std::ptr::drop_in_place(x.as_mut_ptr());
-		}*/
-		}
+	} else if std::mem::needs_drop::<T>() {
+	    // Drop the allocated stack elements in place
+	    unsafe {
+		std::ptr::drop_in_place(std::ptr::slice_from_raw_parts_mut(self.stack_ptr(), self.fill_ptr()));
 	    }
 	}
     }
@@ -87,19 +92,46 @@ impl<'a, T> AVec<'a, T>
 	}
     }
 
+    /// The start of the stack buffer. Only valid if `!is_allocated()`.
+    #[inline(always)] fn stack_ptr(&self) -> *mut T
+    {
+	unsafe {
+	    self.inner.stack.buf_ptr as *mut T
+	}
+    }
+
+    /// The heap buffer.
+    ///
+    /// # Safety
+    /// `is_allocated()` must be true.
+    #[inline(always)] unsafe fn heap(&self) -> &Vec<T>
+    {
+	&self.inner.heap.buf
+    }
+
+    /// The heap buffer.
+    ///
+    /// # Safety
+    /// `is_allocated()` must be true.
+    #[inline(always)] unsafe fn heap_mut(&mut self) -> &mut Vec<T>
+    {
+	&mut (*self.inner.heap).buf
+    }
+
     /// Have the elements been moved to the heap?
     pub fn is_allocated(&self) -> bool
     {
-	self.fill_ptr() >= self.stack_sz
+	self.fill_ptr() > self.stack_sz
     }
-    
+
     /// Create a new `AVec` with this backing buffer.
     pub fn new(stack: &'a mut [MaybeUninit<T>]) -> Self
     {
 	let (buf_ptr, stack_sz) = (stack.as_mut_ptr(), stack.len());
 
 	Self {
-	    stack_sz,
+	    // `usize::MAX` is reserved as the heap buffer's fill_ptr.
+	    stack_sz: stack_sz.min(usize::MAX - 1),
 	    inner: Internal {
 		stack: StackBuffer {
 		    fill_ptr: 0,
@@ -117,44 +149,548 @@ impl<'a, T> AVec<'a, T>
 	};
 	self.inner = Internal {
 	    heap: ManuallyDrop::new(HeapBuffer {
-		_fill_ptr: self.stack_sz,
+		_fill_ptr: usize::MAX,
 		buf,
 	    }),
 	};
     }
-    
+
+    /// Move the elements to the heap if the stack buffer cannot hold another one.
+    #[inline] fn reserve_one(&mut self)
+    {
+	if !self.is_allocated() && self.fill_ptr() == self.stack_sz {
+	    self.move_to_heap();
+	}
+    }
+
     /// Insert an element into this `AVec`.
     pub fn push(&mut self, item: T)
     {
+	self.reserve_one();
 	if self.is_allocated()
 	{
 	    unsafe {
-		(*self.inner.heap).buf.push(item)
+		self.heap_mut().push(item)
 	    }
 	} else {
 	    unsafe {
 		let ptr = self.inner.stack.fill_ptr;
 		*self.inner.stack.buf_ptr.add(ptr) = MaybeUninit::new(item);
 		self.inner.stack.fill_ptr += 1;
+	    }
+	}
+    }
+
+    /// Remove the last element from this `AVec` and return it, or `None` if it is empty.
+    pub fn pop(&mut self) -> Option<T>
+    {
+	if self.is_allocated() {
+	    unsafe {
+		self.heap_mut().pop()
+	    }
+	} else if self.fill_ptr() == 0 {
+	    None
+	} else {
+	    unsafe {
+		self.inner.stack.fill_ptr -= 1;
+		Some(self.stack_ptr().add(self.fill_ptr()).read())
+	    }
+	}
+    }
+
+    /// Insert an element at position `index`, shifting all elements after it to the right.
+    ///
+    /// # Panics
+    /// If `index > len`.
+    pub fn insert(&mut self, index: usize, item: T)
+    {
+	let len = self.len();
+	assert!(index <= len, "insertion index (is {}) should be <= len (is {})", index, len);
+
+	self.reserve_one();
+	if self.is_allocated() {
+	    unsafe {
+		self.heap_mut().insert(index, item)
+	    }
+	} else {
+	    unsafe {
+		let ptr = self.stack_ptr().add(index);
+		ptr::copy(ptr, ptr.add(1), len - index);
+		ptr::write(ptr, item);
+		self.inner.stack.fill_ptr += 1;
+	    }
+	}
+    }
+
+    /// Remove and return the element at position `index`, shifting all elements after it to the left.
+    ///
+    /// # Panics
+    /// If `index` is out of bounds.
+    pub fn remove(&mut self, index: usize) -> T
+    {
+	let len = self.len();
+	assert!(index < len, "removal index (is {}) should be < len (is {})", index, len);
+
+	if self.is_allocated() {
+	    unsafe {
+		self.heap_mut().remove(index)
+	    }
+	} else {
+	    unsafe {
+		let ptr = self.stack_ptr().add(index);
+		let item = ptr::read(ptr);
+		ptr::copy(ptr.add(1), ptr, len - index - 1);
+		self.inner.stack.fill_ptr -= 1;
+		item
+	    }
+	}
+    }
+
+    /// Remove and return the element at position `index`, replacing it with the last element.
+    ///
+    /// This does not preserve ordering, but is O(1).
+    ///
+    /// # Panics
+    /// If `index` is out of bounds.
+    pub fn swap_remove(&mut self, index: usize) -> T
+    {
+	let len = self.len();
+	assert!(index < len, "swap_remove index (is {}) should be < len (is {})", index, len);
 
-		if self.is_allocated() {
-		    // Move all items to heap
-		    self.move_to_heap();
+	if self.is_allocated() {
+	    unsafe {
+		self.heap_mut().swap_remove(index)
+	    }
+	} else {
+	    unsafe {
+		let base = self.stack_ptr();
+		let item = ptr::read(base.add(index));
+		ptr::copy(base.add(len - 1), base.add(index), 1);
+		self.inner.stack.fill_ptr -= 1;
+		item
+	    }
+	}
+    }
+
+    /// Shorten this `AVec` to `len` elements, dropping the rest.
+    ///
+    /// If `len` is greater than the current length, this has no effect.
+    pub fn truncate(&mut self, len: usize)
+    {
+	if self.is_allocated() {
+	    unsafe {
+		self.heap_mut().truncate(len)
+	    }
+	} else {
+	    let old_len = self.fill_ptr();
+	    if len < old_len {
+		unsafe {
+		    // Set the length first, so a panicking destructor cannot cause a double drop.
+		    self.inner.stack.fill_ptr = len;
+		    ptr::drop_in_place(ptr::slice_from_raw_parts_mut(self.stack_ptr().add(len), old_len - len));
 		}
 	    }
 	}
     }
 
+    /// Drop all elements in this `AVec`.
+    ///
+    /// If the elements have been moved to the heap, they stay there.
+    #[inline] pub fn clear(&mut self)
+    {
+	self.truncate(0)
+    }
+
+    /// Retain only the elements for which `f` returns `true`, dropping the rest in place.
+    ///
+    /// The order of the retained elements is preserved.
+    pub fn retain<F>(&mut self, mut f: F)
+    where F: FnMut(&T) -> bool
+    {
+	if self.is_allocated() {
+	    return unsafe {
+		self.heap_mut().retain(f)
+	    };
+	}
+
+	let len = self.fill_ptr();
+	let base = self.stack_ptr();
+	// If `f` or a destructor panics, the elements are leaked instead of being dropped twice.
+	self.inner.stack.fill_ptr = 0;
+	let mut deleted = 0;
+	for i in 0..len {
+	    unsafe {
+		let cur = base.add(i);
+		if f(&*cur) {
+		    if deleted > 0 {
+			ptr::copy_nonoverlapping(cur, cur.sub(deleted), 1);
+		    }
+		} else {
+		    deleted += 1;
+		    ptr::drop_in_place(cur);
+		}
+	    }
+	}
+	self.inner.stack.fill_ptr = len - deleted;
+    }
+
+    /// Remove the elements in `range` from this `AVec`, returning them in an iterator.
+    ///
+    /// The removed elements that the iterator does not consume are dropped when it is dropped.
+    ///
+    /// # Panics
+    /// If the range is out of bounds, or its start is after its end.
+    pub fn drain<R>(&mut self, range: R) -> Drain<'_, 'a, T>
+    where R: RangeBounds<usize>
+    {
+	if self.is_allocated() {
+	    return Drain {
+		inner: DrainInner::Heap(unsafe { self.heap_mut() }.drain(range)),
+	    };
+	}
+
+	let len = self.fill_ptr();
+	let range = slice_range(range, len);
+	// The drained range and tail are not owned by the vector until the `Drain` is dropped.
+	self.inner.stack.fill_ptr = range.start;
+	Drain {
+	    inner: DrainInner::Stack {
+		tail: range.end..len,
+		range,
+		avec: self,
+	    }
+	}
+    }
+
+    /// Clone and append all elements of `other` to this `AVec`.
+    pub fn extend_from_slice(&mut self, other: &[T])
+    where T: Clone
+    {
+	for item in other {
+	    self.push(item.clone());
+	}
+    }
+
     /// The number of elements in this `AVec`.
     pub fn len(&self) -> usize
     {
 	if self.is_allocated()
 	{
 	    unsafe {
-		self.inner.heap.buf.len()
+		self.heap().len()
 	    }
 	} else {
 	    self.fill_ptr()
 	}
     }
+
+    /// Does this `AVec` contain no elements?
+    #[inline] pub fn is_empty(&self) -> bool
+    {
+	self.len() == 0
+    }
+
+    /// The number of elements this `AVec` can hold before it has to (re)allocate.
+    pub fn capacity(&self) -> usize
+    {
+	if self.is_allocated()
+	{
+	    unsafe {
+		self.heap().capacity()
+	    }
+	} else {
+	    self.stack_sz
+	}
+    }
+
+    /// A slice of the elements in this `AVec`.
+    pub fn as_slice(&self) -> &[T]
+    {
+	if self.is_allocated() {
+	    unsafe {
+		&self.heap()[..]
+	    }
+	} else {
+	    // SAFETY: The first `fill_ptr` elements of the stack buffer are initialised.
+	    unsafe {
+		slice::from_raw_parts(self.stack_ptr(), self.fill_ptr())
+	    }
+	}
+    }
+
+    /// A mutable slice of the elements in this `AVec`.
+    pub fn as_mut_slice(&mut self) -> &mut [T]
+    {
+	if self.is_allocated() {
+	    unsafe {
+		&mut self.heap_mut()[..]
+	    }
+	} else {
+	    // SAFETY: The first `fill_ptr` elements of the stack buffer are initialised.
+	    unsafe {
+		slice::from_raw_parts_mut(self.stack_ptr(), self.fill_ptr())
+	    }
+	}
+    }
+}
+
+/// Resolve `range` into a checked `Range` for a slice of length `len`.
+fn slice_range<R>(range: R, len: usize) -> Range<usize>
+where R: RangeBounds<usize>
+{
+    let start = match range.start_bound() {
+	Bound::Included(&start) => start,
+	Bound::Excluded(&start) => start.checked_add(1).expect("attempted to index slice from after maximum usize"),
+	Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+	Bound::Included(&end) => end.checked_add(1).expect("attempted to index slice up to maximum usize"),
+	Bound::Excluded(&end) => end,
+	Bound::Unbounded => len,
+    };
+    assert!(start <= end, "slice index starts at {} but ends at {}", start, end);
+    assert!(end <= len, "range end index {} out of range for slice of length {}", end, len);
+    start..end
+}
+
+impl<'a, T> Deref for AVec<'a, T>
+{
+    type Target = [T];
+    #[inline] fn deref(&self) -> &Self::Target {
+	self.as_slice()
+    }
+}
+
+impl<'a, T> DerefMut for AVec<'a, T>
+{
+    #[inline] fn deref_mut(&mut self) -> &mut Self::Target {
+	self.as_mut_slice()
+    }
+}
+
+impl<'a, T> AsRef<[T]> for AVec<'a, T>
+{
+    #[inline] fn as_ref(&self) -> &[T] {
+	self.as_slice()
+    }
+}
+
+impl<'a, T> AsMut<[T]> for AVec<'a, T>
+{
+    #[inline] fn as_mut(&mut self) -> &mut [T] {
+	self.as_mut_slice()
+    }
+}
+
+impl<'a, T, I: SliceIndex<[T]>> Index<I> for AVec<'a, T>
+{
+    type Output = I::Output;
+    #[inline] fn index(&self, index: I) -> &Self::Output {
+	&self.as_slice()[index]
+    }
+}
+
+impl<'a, T, I: SliceIndex<[T]>> IndexMut<I> for AVec<'a, T>
+{
+    #[inline] fn index_mut(&mut self, index: I) -> &mut Self::Output {
+	&mut self.as_mut_slice()[index]
+    }
+}
+
+impl<'a, T: fmt::Debug> fmt::Debug for AVec<'a, T>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	fmt::Debug::fmt(self.as_slice(), f)
+    }
+}
+
+impl<'a, T> Extend<T> for AVec<'a, T>
+{
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+	for item in iter {
+	    self.push(item);
+	}
+    }
+}
+
+impl<'a, 'b, T: Copy + 'b> Extend<&'b T> for AVec<'a, T>
+{
+    fn extend<I: IntoIterator<Item = &'b T>>(&mut self, iter: I) {
+	for &item in iter {
+	    self.push(item);
+	}
+    }
+}
+
+impl<'a, T> IntoIterator for AVec<'a, T>
+{
+    type Item = T;
+    type IntoIter = IntoIter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+	let mut this = ManuallyDrop::new(self);
+	let inner = if this.is_allocated() {
+	    // SAFETY: `this` is never dropped, so the heap buffer is not used again.
+	    IntoIterInner::Heap(unsafe { ManuallyDrop::take(&mut this.inner.heap) }.buf.into_iter())
+	} else {
+	    IntoIterInner::Stack {
+		buf_ptr: this.stack_ptr(),
+		range: 0..this.fill_ptr(),
+		_stack: PhantomData,
+	    }
+	};
+	IntoIter { inner }
+    }
+}
+
+impl<'v, 'a, T> IntoIterator for &'v AVec<'a, T>
+{
+    type Item = &'v T;
+    type IntoIter = slice::Iter<'v, T>;
+
+    #[inline] fn into_iter(self) -> Self::IntoIter {
+	self.as_slice().iter()
+    }
+}
+
+impl<'v, 'a, T> IntoIterator for &'v mut AVec<'a, T>
+{
+    type Item = &'v mut T;
+    type IntoIter = slice::IterMut<'v, T>;
+
+    #[inline] fn into_iter(self) -> Self::IntoIter {
+	self.as_mut_slice().iter_mut()
+    }
+}
+
+/// An owning iterator over the elements of an `AVec`.
+///
+/// Created by `AVec::into_iter()`.
+pub struct IntoIter<'a, T>
+{
+    inner: IntoIterInner<'a, T>,
+}
+
+enum IntoIterInner<'a, T>
+{
+    Heap(vec::IntoIter<T>),
+    Stack {
+	buf_ptr: *mut T,
+	/// The elements of the stack buffer not yet yielded.
+	range: Range<usize>,
+	_stack: PhantomData<(&'a mut [MaybeUninit<T>], T)>,
+    },
+}
+unsafe impl<'a, T: Send> Send for IntoIter<'a, T>{}
+unsafe impl<'a, T: Sync> Sync for IntoIter<'a, T>{}
+
+impl<'a, T> Iterator for IntoIter<'a, T>
+{
+    type Item = T;
+    fn next(&mut self) -> Option<Self::Item> {
+	match &mut self.inner {
+	    IntoIterInner::Heap(iter) => iter.next(),
+	    IntoIterInner::Stack { buf_ptr, range, .. } => range.next().map(|i| unsafe { buf_ptr.add(i).read() }),
+	}
+    }
+
+    #[inline] fn size_hint(&self) -> (usize, Option<usize>) {
+	match &self.inner {
+	    IntoIterInner::Heap(iter) => iter.size_hint(),
+	    IntoIterInner::Stack { range, .. } => range.size_hint(),
+	}
+    }
+}
+
+impl<'a, T> DoubleEndedIterator for IntoIter<'a, T>
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+	match &mut self.inner {
+	    IntoIterInner::Heap(iter) => iter.next_back(),
+	    IntoIterInner::Stack { buf_ptr, range, .. } => range.next_back().map(|i| unsafe { buf_ptr.add(i).read() }),
+	}
+    }
+}
+impl<'a, T> ExactSizeIterator for IntoIter<'a, T>{}
+impl<'a, T> FusedIterator for IntoIter<'a, T>{}
+
+impl<'a, T> Drop for IntoIter<'a, T>
+{
+    fn drop(&mut self) {
+	if let IntoIterInner::Stack { buf_ptr, range, .. } = &mut self.inner {
+	    // Drop the elements that were not yielded
+	    unsafe {
+		ptr::drop_in_place(ptr::slice_from_raw_parts_mut(buf_ptr.add(range.start), range.len()));
+	    }
+	}
+    }
+}
+
+/// A draining iterator over a range of elements of an `AVec`.
+///
+/// Created by `AVec::drain()`.
+pub struct Drain<'v, 'a, T>
+{
+    inner: DrainInner<'v, 'a, T>,
+}
+
+enum DrainInner<'v, 'a, T>
+{
+    Heap(vec::Drain<'v, T>),
+    Stack {
+	/// The vector being drained. Its `fill_ptr` is set to the start of the drained range.
+	avec: &'v mut AVec<'a, T>,
+	/// The elements of the drained range not yet yielded.
+	range: Range<usize>,
+	/// The elements after the drained range, moved back once the `Drain` is dropped.
+	tail: Range<usize>,
+    },
+}
+
+impl<'v, 'a, T> Iterator for Drain<'v, 'a, T>
+{
+    type Item = T;
+    fn next(&mut self) -> Option<Self::Item> {
+	match &mut self.inner {
+	    DrainInner::Heap(iter) => iter.next(),
+	    DrainInner::Stack { avec, range, .. } => range.next().map(|i| unsafe { avec.stack_ptr().add(i).read() }),
+	}
+    }
+
+    #[inline] fn size_hint(&self) -> (usize, Option<usize>) {
+	match &self.inner {
+	    DrainInner::Heap(iter) => iter.size_hint(),
+	    DrainInner::Stack { range, .. } => range.size_hint(),
+	}
+    }
+}
+
+impl<'v, 'a, T> DoubleEndedIterator for Drain<'v, 'a, T>
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+	match &mut self.inner {
+	    DrainInner::Heap(iter) => iter.next_back(),
+	    DrainInner::Stack { avec, range, .. } => range.next_back().map(|i| unsafe { avec.stack_ptr().add(i).read() }),
+	}
+    }
+}
+impl<'v, 'a, T> ExactSizeIterator for Drain<'v, 'a, T>{}
+impl<'v, 'a, T> FusedIterator for Drain<'v, 'a, T>{}
+
+impl<'v, 'a, T> Drop for Drain<'v, 'a, T>
+{
+    fn drop(&mut self) {
+	if let DrainInner::Stack { avec, range, tail } = &mut self.inner {
+	    let base = avec.stack_ptr();
+	    unsafe {
+		// Drop the elements that were not yielded
+		ptr::drop_in_place(ptr::slice_from_raw_parts_mut(base.add(range.start), range.len()));
+
+		// Move the tail back to the end of the vector
+		let start = avec.fill_ptr();
+		ptr::copy(base.add(tail.start), base.add(start), tail.len());
+		avec.inner.stack.fill_ptr = start + tail.len();
+	    }
+	}
+    }
 }
@@ -0,0 +1,113 @@
+//! Functions that use an inline array for allocations of up to `N` elements, and only go through the trampoline for larger ones.
+//!
+//! For small sizes, the call through the FFI trampoline costs more than the allocation itself. When an upper bound for the common case is known, these functions reserve `[MaybeUninit<T>; N]` in their own frame and use it directly if `size <= N`.
+//! Note that the array is reserved on the stack whether or not it is used, so `N` should be kept small.
+//! Allocations that use the array still count against the stack budget (see `budget`) and are recorded by `stats`, just like those that go through the trampoline.
+//!
+//! # Example
+//! ```
+//! # use stackalloc::stackalloc_bounded;
+//! fn sum_of_squares(n: usize) -> u64
+//! {
+//!   // Allocations of up to 64 elements do not call into the trampoline.
+//!   stackalloc_bounded::<64, _, _, _>(n, 0u64, |buf| {
+//!     buf.iter_mut().zip(0..).for_each(|(x, i)| *x = i * i);
+//!     buf.iter().sum()
+//!   })
+//! }
+//! # assert_eq!(sum_of_squares(10), 285);
+//! # assert_eq!(sum_of_squares(100), 328350);
+//! ```
+use super::*;
+
+/// Allocate a runtime length uninitialised byte buffer in an inline array if `size <= N` (or on the stack through the trampoline if not), call `callback` with this buffer, and then deallocate the buffer.
+///
+/// See `alloca()`.
+#[inline] pub fn alloca_bounded<const N: usize, T, F>(size: usize, callback: F) -> T
+where F: FnOnce(&mut [MaybeUninit<u8>]) -> T
+{
+    stackalloc_uninit_bounded::<N, u8, T, F>(size, callback)
+}
+
+/// Allocate a runtime length zeroed byte buffer in an inline array if `size <= N` (or on the stack through the trampoline if not), call `callback` with this buffer, and then deallocate the buffer.
+///
+/// See `alloca_zeroed()`.
+#[inline] pub fn alloca_zeroed_bounded<const N: usize, T, F>(size: usize, callback: F) -> T
+where F: FnOnce(&mut [u8]) -> T
+{
+    alloca_bounded::<N, _, _>(size, move |buf| {
+	// SAFETY: We zero-initialise the backing slice
+	callback(unsafe {
+	    ptr::write_bytes(buf.as_mut_ptr(), 0, buf.len());
+	    helpers::slice_assume_init_mut(buf)
+	})
+    })
+}
+
+/// Allocate a runtime length slice of uninitialised `T` in an inline array if `size <= N` (or on the stack through the trampoline if not), call `callback` with this buffer, and then deallocate the buffer.
+///
+/// See `stackalloc_uninit()`.
+#[inline] pub fn stackalloc_uninit_bounded<const N: usize, T, U, F>(size: usize, callback: F) -> U
+where F: FnOnce(&mut [MaybeUninit<T>]) -> U
+{
+    // Zero-sized types are never allocated or counted (see `stackalloc_uninit()`.)
+    if size <= N && mem::size_of::<T>() != 0 {
+	// Cannot overflow, since an array of `N` elements fits in memory.
+	let bytes = size * mem::size_of::<T>();
+	#[cfg(not(feature = "no_std"))]
+	if !budget::allows(bytes) {
+	    return fallback::heap_uninit(size, callback);
+	}
+
+	#[cfg(all(feature = "stats", not(feature = "no_std")))]
+	let _record = stats::Record::new(bytes);
+	#[cfg(not(feature = "no_std"))]
+	let _charge = budget::Charge::new(bytes);
+
+	// SAFETY: An array of `MaybeUninit` does not need to be initialised.
+	let mut buf: [MaybeUninit<T>; N] = unsafe { MaybeUninit::uninit().assume_init() };
+	callback(&mut buf[..size])
+    } else {
+	stackalloc_uninit(size, callback)
+    }
+}
+
+/// Allocate a runtime length slice of `T` in an inline array if `size <= N` (or on the stack through the trampoline if not), fill it by calling `init_with`, call `callback` with this buffer, and then drop and deallocate the buffer.
+///
+/// See `stackalloc_with()`.
+#[inline] pub fn stackalloc_with_bounded<const N: usize, T, U, F, I>(size: usize, init_with: I, callback: F) -> U
+where F: FnOnce(&mut [T]) -> U,
+      I: FnMut() -> T
+{
+    stackalloc_uninit_bounded::<N, _, _, _>(size, move |buf| init_with_in(buf, init_with, callback))
+}
+
+/// Allocate a runtime length slice of `T` in an inline array if `size <= N` (or on the stack through the trampoline if not), fill it by cloning `init`, call `callback` with this buffer, and then drop and deallocate the buffer.
+///
+/// See `stackalloc()`.
+#[inline] pub fn stackalloc_bounded<const N: usize, T, U, F>(size: usize, init: T, callback: F) -> U
+where F: FnOnce(&mut [T]) -> U,
+      T: Clone
+{
+    stackalloc_with_bounded::<N, _, _, _, _>(size, move || init.clone(), callback)
+}
+
+/// Allocate a runtime length slice of `T` in an inline array if `size <= N` (or on the stack through the trampoline if not), fill it by calling `T::default()`, call `callback` with this buffer, and then drop and deallocate the buffer.
+///
+/// See `stackalloc_with_default()`.
+#[inline] pub fn stackalloc_with_default_bounded<const N: usize, T, U, F>(size: usize, callback: F) -> U
+where F: FnOnce(&mut [T]) -> U,
+      T: Default
+{
+    stackalloc_with_bounded::<N, _, _, _, _>(size, T::default, callback)
+}
+
+/// Collect an iterator into a buffer of up to `size` elements in an inline array if `size <= N` (or on the stack through the trampoline if not), call `callback` with this buffer, and then drop and deallocate the buffer.
+///
+/// See `stackalloc_with_iter()`.
+#[inline] pub fn stackalloc_with_iter_bounded<const N: usize, I, T, U, F>(size: usize, iter: I, callback: F) -> U
+where F: FnOnce(&mut [T]) -> U,
+      I: IntoIterator<Item = T>,
+{
+    stackalloc_uninit_bounded::<N, _, _, _>(size, move |buf| init_from_iter_in(buf, iter, callback))
+}
@@ -0,0 +1,171 @@
+//! Limiting how much stack space this crate may use on a thread.
+//!
+//! Every allocation made through `alloca()` (and so every other function in this crate that allocates on the stack, including the inline array of the `_bounded` functions) counts against the current thread's budget until it is deallocated.
+//! Once an allocation would exceed the budget, the `try_` functions fail, and the other functions allocate on the heap instead.
+//!
+//! Every limit applies to one thread at a time: there is no cap on the total stack space used by all threads together.
+//! There is no limit by default. A default limit for every thread can be set with `set_default_thread_limit()`, overridden for one thread with `set_thread_limit()`, or narrowed for the duration of a closure with `with_budget()`.
+//!
+//! Allocations are only counted once a limit has been set for the first time (by any of these), so the budget costs nothing when it is not used.
+//! From then on, every allocation is counted for the rest of the process, even if the limits are cleared again.
+//!
+//! # Example
+//! ```
+//! # use stackalloc::{budget, try_alloca};
+//! fn parse(depth: usize) -> usize
+//! {
+//!   // Each level needs 256 bytes of scratch space, and stops recursing when it cannot get it.
+//!   try_alloca(256, |_| parse(depth + 1)).unwrap_or(depth)
+//! }
+//! assert_eq!(budget::with_budget(1024, || parse(0)), 4);
+//! ```
+use std::cell::Cell;
+use core::sync::atomic::{
+    AtomicBool,
+    AtomicUsize,
+    Ordering,
+};
+
+/// `DEFAULT_LIMIT` when there is no default limit.
+const NO_LIMIT: usize = usize::MAX;
+
+static DEFAULT_LIMIT: AtomicUsize = AtomicUsize::new(NO_LIMIT);
+
+/// Set when a limit is set for the first time. Until then, allocations are not counted. It is never unset.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    static THREAD_LIMIT: Cell<Option<usize>> = const { Cell::new(None) };
+    static USED: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Set the number of bytes this crate may use on the stack of each thread that does not have its own limit.
+///
+/// This is a default for each thread separately, not a cap on the total of all threads: `n` threads may use `bytes` bytes each.
+#[inline] pub fn set_default_thread_limit(bytes: usize)
+{
+    enable();
+    DEFAULT_LIMIT.store(bytes, Ordering::Relaxed)
+}
+
+/// Remove the limit set by `set_default_thread_limit()`.
+///
+/// Allocations are still counted afterwards (see the module documentation.)
+#[inline] pub fn clear_default_thread_limit()
+{
+    DEFAULT_LIMIT.store(NO_LIMIT, Ordering::Relaxed)
+}
+
+/// Set the number of bytes this crate may use on the stack of the current thread, instead of the default limit.
+#[inline] pub fn set_thread_limit(bytes: usize)
+{
+    enable();
+    THREAD_LIMIT.with(|limit| limit.set(Some(bytes)))
+}
+
+/// Remove the limit set by `set_thread_limit()`, so the default limit applies to the current thread again.
+///
+/// Allocations are still counted afterwards (see the module documentation.)
+#[inline] pub fn clear_thread_limit()
+{
+    THREAD_LIMIT.with(|limit| limit.set(None))
+}
+
+/// The number of bytes this crate may use on the stack of the current thread, or `None` if there is no limit.
+#[inline] pub fn limit() -> Option<usize>
+{
+    if !enabled() {
+	return None;
+    }
+    THREAD_LIMIT.try_with(Cell::get).ok().flatten().or_else(|| {
+	match DEFAULT_LIMIT.load(Ordering::Relaxed) {
+	    NO_LIMIT => None,
+	    bytes => Some(bytes),
+	}
+    })
+}
+
+/// The number of bytes currently allocated on the stack of the current thread by this crate.
+///
+/// Allocations made before a limit was first set are not counted.
+#[inline] pub fn used() -> usize
+{
+    USED.try_with(Cell::get).unwrap_or(0)
+}
+
+/// The number of bytes that can still be allocated on the stack of the current thread before the limit is reached, or `None` if there is no limit.
+#[inline] pub fn available() -> Option<usize>
+{
+    limit().map(|limit| limit.saturating_sub(used()))
+}
+
+/// Run `callback` with the limit of the current thread set to allow at most `bytes` more bytes to be allocated (or fewer, if the current limit is lower), and then restore the previous limit.
+pub fn with_budget<U, F>(bytes: usize, callback: F) -> U
+where F: FnOnce() -> U
+{
+    /// Restores the previous thread limit, even if `callback` panics.
+    struct Restore(Option<usize>);
+    impl Drop for Restore
+    {
+	fn drop(&mut self)
+	{
+	    let _ = THREAD_LIMIT.try_with(|limit| limit.set(self.0));
+	}
+    }
+
+    enable();
+    let bytes = available().map_or(bytes, |available| available.min(bytes));
+    let _restore = Restore(THREAD_LIMIT.with(|limit| limit.replace(Some(used().saturating_add(bytes)))));
+    callback()
+}
+
+/// Start counting allocations.
+#[inline] fn enable()
+{
+    ENABLED.store(true, Ordering::Relaxed)
+}
+
+/// Has a limit ever been set?
+#[inline(always)] fn enabled() -> bool
+{
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Can `size` bytes be allocated on the stack without exceeding the limit?
+#[inline] pub(crate) fn allows(size: usize) -> bool
+{
+    if !enabled() {
+	return true;
+    }
+    match available() {
+	Some(available) => size <= available,
+	None => true,
+    }
+}
+
+/// Counts an allocation of `size` bytes against the current thread's budget until it is dropped.
+///
+/// Nothing is counted if no limit has been set yet.
+pub(crate) struct Charge(Option<usize>);
+
+impl Charge
+{
+    #[inline] pub(crate) fn new(size: usize) -> Self
+    {
+	if !enabled() {
+	    return Self(None);
+	}
+	let _ = USED.try_with(|used| used.set(used.get().saturating_add(size)));
+	Self(Some(size))
+    }
+}
+
+impl Drop for Charge
+{
+    #[inline] fn drop(&mut self)
+    {
+	if let Some(size) = self.0 {
+	    let _ = USED.try_with(|used| used.set(used.get().saturating_sub(size)));
+	}
+    }
+}
@@ -0,0 +1,89 @@
+//! A bump allocator over a byte buffer, used by `StackArena` and `StackAllocator`.
+use super::*;
+use core::cell::Cell;
+
+/// Hands out aligned sub-regions of a buffer from the start to the end, and never reuses them.
+pub(crate) struct Bump
+{
+    base: Cell<*mut u8>,
+    len: Cell<usize>,
+    offset: Cell<usize>,
+}
+
+impl Bump
+{
+    /// Create a new bump allocator over `len` bytes at `base`.
+    ///
+    /// # Safety
+    /// `base` must be non-null and valid for reads and writes of `len` bytes for as long as allocations from this are used.
+    #[inline] pub(crate) unsafe fn new(base: *mut u8, len: usize) -> Self
+    {
+	Self {
+	    base: Cell::new(base),
+	    len: Cell::new(len),
+	    offset: Cell::new(0),
+	}
+    }
+
+    /// Allocate from `len` bytes at `base` from now on, instead of the current buffer.
+    ///
+    /// # Safety
+    /// See `Bump::new()`.
+    #[inline] pub(crate) unsafe fn switch_to(&self, base: *mut u8, len: usize)
+    {
+	self.base.set(base);
+	self.len.set(len);
+	self.offset.set(0);
+    }
+
+    /// Allocate a region for `layout` from the current buffer, or return `None` if there is not enough space left in it.
+    ///
+    /// The returned pointer is non-null and aligned to `layout.align()`.
+    #[inline] pub(crate) fn alloc(&self, layout: Layout) -> Option<ptr::NonNull<u8>>
+    {
+	let offset = self.offset.get();
+	// SAFETY: `offset` is never more than `len`.
+	let start = offset.checked_add(align_padding(unsafe { self.base.get().add(offset) }, layout.align()))?;
+	let end = start.checked_add(layout.size())?;
+	if end > self.len.get() {
+	    return None;
+	}
+	self.offset.set(end);
+	// SAFETY: `start..end` is within the buffer, and `base` is non-null.
+	Some(unsafe { ptr::NonNull::new_unchecked(self.base.get().add(start)) })
+    }
+
+    /// Is `ptr..ptr+size` the most recent allocation from the current buffer?
+    #[inline] pub(crate) fn is_last(&self, ptr: *mut u8, size: usize) -> bool
+    {
+	(ptr as usize).wrapping_add(size) == (self.base.get() as usize).wrapping_add(self.offset.get())
+    }
+
+    /// Resize the most recent allocation at `ptr` to `new_size` bytes in place, or return `false` if there is not enough space left.
+    ///
+    /// # Safety
+    /// `ptr` must be the most recent allocation from the current buffer (see `is_last()`.)
+    #[inline] pub(crate) unsafe fn resize_last(&self, ptr: *mut u8, new_size: usize) -> bool
+    {
+	let start = ptr as usize - self.base.get() as usize;
+	match start.checked_add(new_size) {
+	    Some(end) if end <= self.len.get() => {
+		self.offset.set(end);
+		true
+	    },
+	    _ => false,
+	}
+    }
+
+    /// The number of bytes that have been allocated from the current buffer, including alignment padding.
+    #[inline] pub(crate) fn used(&self) -> usize
+    {
+	self.offset.get()
+    }
+
+    /// The number of bytes left in the current buffer.
+    #[inline] pub(crate) fn remaining(&self) -> usize
+    {
+	self.len.get() - self.offset.get()
+    }
+}
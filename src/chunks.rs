@@ -0,0 +1,75 @@
+//! Processing a stream in fixed-size batches, reusing one stack allocated buffer for the whole stream.
+//!
+//! # Example
+//! ```
+//! # use stackalloc::chunks;
+//! use core::ops::ControlFlow;
+//!
+//! let mut sums = Vec::new();
+//! let flow = chunks((1..=10).map(|x| x * x), 4, |batch: &mut [u32]| {
+//!   sums.push(batch.iter().sum::<u32>());
+//!   ControlFlow::<()>::Continue(())
+//! });
+//! assert_eq!(flow, ControlFlow::Continue(()));
+//! assert_eq!(sums, [30, 174, 181]);
+//! ```
+use super::*;
+use core::ops::ControlFlow;
+#[cfg(not(feature = "no_std"))]
+use std::io;
+
+/// Allocate a runtime length slice of `chunk_len` elements of `T` on the stack, and then repeatedly fill it from `iter` and call `callback` with the filled part of it, until `iter` is exhausted or `callback` breaks.
+///
+/// Each batch's elements are dropped after `callback` returns, before the buffer is filled again. Only the last batch may be shorter than `chunk_len`, and `callback` is never called with an empty batch.
+/// `iter` is not advanced any further once `callback` breaks.
+///
+/// See `stackalloc_with_iter()`.
+///
+/// # Panics
+/// If `chunk_len` is 0, or the size of the buffer overflows (see `Layout::array()`.)
+pub fn chunks<I, T, B, F>(iter: I, chunk_len: usize, mut callback: F) -> ControlFlow<B>
+where F: FnMut(&mut [T]) -> ControlFlow<B>,
+      I: IntoIterator<Item = T>,
+{
+    assert!(chunk_len != 0, "chunk_len must not be zero");
+    let mut iter = iter.into_iter();
+    stackalloc_uninit(chunk_len, move |buf| {
+	loop {
+	    let (len, flow) = init_from_iter_in(buf, iter.by_ref(), |batch| match batch.len() {
+		0 => (0, ControlFlow::Continue(())),
+		len => (len, callback(batch)),
+	    });
+	    if flow.is_break() || len < chunk_len {
+		break flow;
+	    }
+	}
+    })
+}
+
+/// Allocate a runtime length zeroed byte buffer of `bufsize` bytes on the stack, and then repeatedly read into it from `reader` and call `callback` with the bytes read, until the end of the stream is reached or `callback` breaks.
+///
+/// Reads that are interrupted (see `io::ErrorKind::Interrupted`) are retried. Any other read error is returned.
+///
+/// See `alloca_zeroed()`.
+///
+/// # Panics
+/// If `bufsize` is 0.
+#[cfg(not(feature = "no_std"))]
+pub fn read_chunks<R, B, F>(mut reader: R, bufsize: usize, mut callback: F) -> io::Result<ControlFlow<B>>
+where F: FnMut(&mut [u8]) -> ControlFlow<B>,
+      R: io::Read,
+{
+    assert!(bufsize != 0, "bufsize must not be zero");
+    alloca_zeroed(bufsize, move |buf| {
+	loop {
+	    match reader.read(buf) {
+		Ok(0) => break Ok(ControlFlow::Continue(())),
+		Ok(read) => if let ControlFlow::Break(b) = callback(&mut buf[..read]) {
+		    break Ok(ControlFlow::Break(b));
+		},
+		Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+		Err(err) => break Err(err),
+	    }
+	}
+    })
+}
@@ -0,0 +1,189 @@
+//! Collecting iterators of unknown length on the stack.
+//!
+//! `stackalloc_collect()` does not trust the iterator's `size_hint()` (which is only used, up to a small bound, to size the first chunk.) It fills a chain of stack allocated chunks, each in a nested frame and each about as large as all the previous ones together, until the iterator is exhausted.
+//! The chunks are then moved into one contiguous stack allocated buffer (unless everything fit in the first chunk, which is used directly.)
+//! This needs about twice as much stack space as the elements themselves. `stackalloc_collect_or_heap()` moves everything to the heap instead once a `Threshold` no longer allows that.
+//!
+//! # Example
+//! ```
+//! # use stackalloc::stackalloc_collect;
+//! let words = "the quick brown fox jumps over the lazy dog";
+//! let longest = stackalloc_collect(words.split(' ').filter(|w| w.len() > 3), |words| {
+//!   words.sort_by_key(|w| w.len());
+//!   words.last().copied()
+//! });
+//! assert_eq!(longest, Some("jumps"));
+//! ```
+use super::*;
+use core::cell::Cell;
+#[cfg(not(feature = "no_std"))]
+use super::fallback::Threshold;
+
+/// The size in bytes of the smallest chunk `collect_in()` allocates.
+const MIN_CHUNK_BYTES: usize = 256;
+
+/// The largest size in bytes the iterator's `size_hint()` can make a chunk, so a wrong hint cannot cause a huge allocation.
+const MAX_HINT_BYTES: usize = 4096;
+
+/// A chunk of the elements collected so far, in a frame of `collect_in()`.
+///
+/// The first `len` elements at `ptr` are dropped when this is dropped, unless they have been moved out of it.
+struct Chunk<'a, T>
+{
+    ptr: *mut T,
+    len: Cell<usize>,
+    prev: Option<&'a Chunk<'a, T>>,
+}
+
+impl<'a, T> Drop for Chunk<'a, T>
+{
+    #[inline] fn drop(&mut self)
+    {
+	// SAFETY: The first `len` elements at `ptr` have been initialised, and nothing else drops them.
+	unsafe {
+	    ptr::drop_in_place(ptr::slice_from_raw_parts_mut(self.ptr, self.len.get()));
+	}
+    }
+}
+
+/// The number of elements of the next chunk, when `total` elements have been collected so far and `hint` more are expected.
+///
+/// Chunks grow geometrically with `total`. `hint` is capped at `MAX_HINT_BYTES`.
+#[inline] fn chunk_len<T>(total: usize, hint: usize) -> usize
+{
+    match mem::size_of::<T>() {
+	0 => usize::MAX,
+	size => total.max(hint.min(MAX_HINT_BYTES / size)).max(MIN_CHUNK_BYTES / size).max(1),
+    }
+}
+
+/// The number of bytes of stack space needed to collect `len` elements of `T`: the chunks, and the contiguous buffer they are moved into.
+#[cfg(not(feature = "no_std"))]
+#[inline] fn stack_bytes<T>(len: usize) -> usize
+{
+    len.saturating_mul(mem::size_of::<T>()).saturating_mul(2)
+}
+
+/// Move the elements of `last` and the chunks before it to `dst`, leaving the chunks empty.
+///
+/// # Safety
+/// `dst` must be valid for writing `total` elements, which must be the number of elements in the chunks.
+unsafe fn move_chunks<T>(mut last: Option<&Chunk<'_, T>>, total: usize, dst: *mut T)
+{
+    let mut end = total;
+    while let Some(chunk) = last {
+	let len = chunk.len.replace(0);
+	end -= len;
+	ptr::copy_nonoverlapping(chunk.ptr, dst.add(end), len);
+	last = chunk.prev;
+    }
+    debug_assert_eq!(end, 0);
+}
+
+/// Call `callback` with the `total` elements collected in `last` and the chunks before it, and then drop them.
+fn finish<T, U, F>(last: &Chunk<'_, T>, total: usize, callback: F) -> U
+where F: FnOnce(&mut [T]) -> U
+{
+    // The iterator may have ended exactly at the end of the previous chunk.
+    let last = match last.prev {
+	Some(prev) if last.len.get() == 0 => prev,
+	_ => last,
+    };
+    if last.prev.is_none() {
+	// SAFETY: The chunk's elements are initialised, and it drops them after `callback` returns.
+	return callback(unsafe { slice::from_raw_parts_mut(last.ptr, last.len.get()) });
+    }
+
+    stackalloc_uninit::<T, _, _>(total, move |buf| {
+	let mut guard = InitGuard { ptr: buf.as_mut_ptr() as *mut T, len: 0 };
+	// SAFETY: `buf` holds `total` elements, and the chunks give up ownership of theirs to `guard`.
+	unsafe {
+	    move_chunks(Some(last), total, guard.ptr);
+	}
+	guard.len = total;
+	// SAFETY: We have just moved `total` elements into the buffer.
+	callback(unsafe { slice::from_raw_parts_mut(guard.ptr, guard.len) })
+    })
+}
+
+/// Move the elements collected in `last` and the chunks before it, and the rest of `iter`, into a `Vec`, and call `callback` with it.
+#[cfg(not(feature = "no_std"))]
+#[inline(never)] fn spill<I, T, U, F>(iter: I, last: Option<&Chunk<'_, T>>, total: usize, callback: F) -> U
+where F: FnOnce(&mut [T]) -> U,
+      I: Iterator<Item = T>
+{
+    let mut vec = Vec::with_capacity(total);
+    // SAFETY: `vec` has capacity for `total` elements, and the chunks give up ownership of theirs to it.
+    unsafe {
+	move_chunks(last, total, vec.as_mut_ptr());
+	vec.set_len(total);
+    }
+    vec.extend(iter);
+    callback(&mut vec[..])
+}
+
+/// Collect the rest of `iter` into a new chunk after `prev` (and more after that, if needed), and call `callback` with all the elements collected.
+///
+/// A new chunk is only allocated on the stack if `allows` returns `true` for the stack space needed in total, otherwise everything is moved to the heap.
+#[cfg_attr(feature = "no_std", allow(unused_variables, clippy::only_used_in_recursion))]
+fn collect_in<I, T, U, F, A>(mut iter: I, prev: Option<&Chunk<'_, T>>, total: usize, allows: &A, callback: F) -> U
+where F: FnOnce(&mut [T]) -> U,
+      I: Iterator<Item = T>,
+      A: Fn(usize) -> bool
+{
+    let len = chunk_len::<T>(total, iter.size_hint().0);
+    #[cfg(not(feature = "no_std"))]
+    if !allows(stack_bytes::<T>(total.saturating_add(len))) {
+	return spill(iter, prev, total, callback);
+    }
+
+    stackalloc_uninit::<T, _, _>(len, move |buf| {
+	let chunk = Chunk {
+	    ptr: buf.as_mut_ptr() as *mut T,
+	    len: Cell::new(0),
+	    prev,
+	};
+	for item in iter.by_ref().take(len)
+	{
+	    // SAFETY: `chunk.len` is within `buf`, and only `chunk` is used to access `buf` from here on.
+	    unsafe {
+		chunk.ptr.add(chunk.len.get()).write(item);
+	    }
+	    chunk.len.set(chunk.len.get() + 1);
+	}
+
+	let total = total + chunk.len.get();
+	if chunk.len.get() < len {
+	    finish(&chunk, total, callback)
+	} else {
+	    collect_in(iter, Some(&chunk), total, allows, callback)
+	}
+    })
+}
+
+/// Collect an iterator of any length into a stack allocated slice, call `callback` with this buffer, and then drop and deallocate the buffer.
+///
+/// Unlike `stackalloc_with_iter()`, every element of the iterator is collected, and its `size_hint()` is only used to size the first chunk (up to a small bound.)
+/// See the `collect` module.
+///
+/// # Panics
+/// If the size of the buffer overflows (see `Layout::array()`.)
+#[inline] pub fn stackalloc_collect<I, T, U, F>(iter: I, callback: F) -> U
+where F: FnOnce(&mut [T]) -> U,
+      I: IntoIterator<Item = T>,
+{
+    collect_in(iter.into_iter(), None, 0, &|_| true, callback)
+}
+
+/// Collect an iterator of any length into a slice on the stack while `threshold` allows it (or on the heap once it does not), call `callback` with this buffer, and then drop and deallocate the buffer.
+///
+/// `threshold` is checked against twice the size of the elements collected so far (see the `collect` module.)
+///
+/// See `stackalloc_collect()`.
+#[cfg(not(feature = "no_std"))]
+#[inline] pub fn stackalloc_collect_or_heap<I, T, U, F>(iter: I, threshold: Threshold, callback: F) -> U
+where F: FnOnce(&mut [T]) -> U,
+      I: IntoIterator<Item = T>,
+{
+    collect_in(iter.into_iter(), None, 0, &|bytes| threshold.allows(bytes), callback)
+}
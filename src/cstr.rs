@@ -0,0 +1,93 @@
+//! Building NUL-terminated C strings on the stack, for passing to FFI functions without allocating.
+use super::*;
+use core::{
+    ffi::CStr,
+    fmt,
+};
+
+/// Returned when the bytes passed to `with_cstr()` (or one of its variants) contain a NUL byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InteriorNulError
+{
+    position: usize,
+}
+
+impl InteriorNulError
+{
+    /// The position of the first NUL byte in the input.
+    #[inline] pub fn nul_position(&self) -> usize
+    {
+	self.position
+    }
+}
+
+impl fmt::Display for InteriorNulError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+	write!(f, "nul byte found in provided data at position: {}", self.position)
+    }
+}
+
+#[cfg(not(feature = "no_std"))]
+impl std::error::Error for InteriorNulError{}
+
+/// Copy `bytes` and a NUL terminator into a stack allocated buffer, call `callback` with it as a `CStr`, and then deallocate the buffer.
+///
+/// `bytes` must not contain any NUL bytes, the terminator is added by this function.
+/// If it does, `callback` is not called and an error is returned instead.
+///
+/// See `alloca()`.
+///
+/// # Example
+/// ```
+/// # use stackalloc::with_cstr;
+/// let len = with_cstr("hello", |cstr| cstr.to_bytes_with_nul().len());
+/// assert_eq!(len, Ok(6));
+/// assert_eq!(with_cstr(b"hel\0lo", |_| ()).unwrap_err().nul_position(), 3);
+/// ```
+#[inline] pub fn with_cstr<B, U, F>(bytes: B, callback: F) -> Result<U, InteriorNulError>
+where B: AsRef<[u8]>,
+      F: FnOnce(&CStr) -> U
+{
+    let bytes = bytes.as_ref();
+    if let Some(position) = bytes.iter().position(|&b| b == 0) {
+	return Err(InteriorNulError { position });
+    }
+
+    // A slice can never be `usize::MAX` bytes long, so this cannot overflow.
+    Ok(alloca(bytes.len() + 1, move |buf| {
+	let ptr = buf.as_mut_ptr() as *mut u8;
+	// SAFETY: `buf` is `bytes.len() + 1` bytes long, and we have checked `bytes` contains no NUL bytes before the terminator we add.
+	unsafe {
+	    ptr::copy_nonoverlapping(bytes.as_ptr(), ptr, bytes.len());
+	    *ptr.add(bytes.len()) = 0;
+	    callback(CStr::from_bytes_with_nul_unchecked(slice::from_raw_parts(ptr, bytes.len() + 1)))
+	}
+    }))
+}
+
+/// Copy an `OsStr` and a NUL terminator into a stack allocated buffer, call `callback` with it as a `CStr`, and then deallocate the buffer.
+///
+/// See `with_cstr()`.
+#[cfg(all(unix, not(feature = "no_std")))]
+#[inline] pub fn with_os_cstr<S, U, F>(string: S, callback: F) -> Result<U, InteriorNulError>
+where S: AsRef<std::ffi::OsStr>,
+      F: FnOnce(&CStr) -> U
+{
+    use std::os::unix::ffi::OsStrExt;
+    with_cstr(string.as_ref().as_bytes(), callback)
+}
+
+/// Copy a `Path` and a NUL terminator into a stack allocated buffer, call `callback` with it as a `CStr`, and then deallocate the buffer.
+///
+/// This is useful for passing paths to syscalls.
+///
+/// See `with_cstr()`.
+#[cfg(all(unix, not(feature = "no_std")))]
+#[inline] pub fn with_path_cstr<P, U, F>(path: P, callback: F) -> Result<U, InteriorNulError>
+where P: AsRef<std::path::Path>,
+      F: FnOnce(&CStr) -> U
+{
+    with_os_cstr(path.as_ref().as_os_str(), callback)
+}
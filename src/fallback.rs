@@ -0,0 +1,159 @@
+//! Functions that use the stack for small allocations, and fall back to the heap for larger ones.
+//!
+//! Each function takes a `Threshold` that decides which one is used for the requested size.
+//! The callback receives the same slice type in both cases, so it does not need to know where the buffer lives.
+//!
+//! # Example
+//! ```
+//! # use stackalloc::{alloca_zeroed_or_heap, Threshold};
+//! fn checksum(len_from_header: usize) -> u64
+//! {
+//!   alloca_zeroed_or_heap(len_from_header, Threshold::Fixed(4096), |buf| {
+//!     buf.iter().map(|&x| x as u64).sum()
+//!   })
+//! }
+//! # assert_eq!(checksum(100), 0);
+//! # assert_eq!(checksum(1024 * 1024), 0);
+//! ```
+use super::*;
+
+/// Decides whether the `_or_heap` functions allocate on the stack or on the heap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Threshold
+{
+    /// Allocate on the stack if the allocation is at most this many bytes.
+    Fixed(usize),
+    /// Allocate on the stack if the allocation would leave at least `stack::safety_margin()` bytes of stack space (see `try_alloca()`.)
+    ///
+    /// If the remaining stack space cannot be determined on this platform, this behaves as `Fixed(Threshold::FALLBACK_BYTES)`.
+    Remaining,
+}
+
+impl Threshold
+{
+    /// The number of bytes `Threshold::Remaining` allows on the stack on platforms where the remaining stack space cannot be determined.
+    pub const FALLBACK_BYTES: usize = 16 * 1024;
+
+    /// Should an allocation of `bytes` bytes be made on the stack?
+    pub fn allows(&self, bytes: usize) -> bool
+    {
+	match self {
+	    Self::Fixed(max) => bytes <= *max,
+	    Self::Remaining => match stack::available() {
+		Some(available) => bytes <= available,
+		None => bytes <= Self::FALLBACK_BYTES,
+	    },
+	}
+    }
+}
+
+impl Default for Threshold
+{
+    #[inline] fn default() -> Self
+    {
+	Self::Remaining
+    }
+}
+
+impl From<usize> for Threshold
+{
+    #[inline] fn from(from: usize) -> Self
+    {
+	Self::Fixed(from)
+    }
+}
+
+/// Allocate a runtime length slice of uninitialised `T` on the heap, call `callback` with this buffer, and then deallocate the buffer.
+#[inline(never)] pub(crate) fn heap_uninit<T, U, F>(size: usize, callback: F) -> U
+where F: FnOnce(&mut [MaybeUninit<T>]) -> U
+{
+    let mut buf: Vec<MaybeUninit<T>> = Vec::with_capacity(size);
+    // SAFETY: `MaybeUninit<T>` does not need to be initialised.
+    unsafe {
+	buf.set_len(size);
+    }
+    callback(&mut buf[..])
+}
+
+/// Allocate a runtime length uninitialised byte buffer on the stack if `threshold` allows it (or on the heap if not), call `callback` with this buffer, and then deallocate the buffer.
+///
+/// See `alloca()`.
+#[inline] pub fn alloca_or_heap<T, F>(size: usize, threshold: Threshold, callback: F) -> T
+where F: FnOnce(&mut [MaybeUninit<u8>]) -> T
+{
+    if threshold.allows(size) {
+	alloca(size, callback)
+    } else {
+	heap_uninit(size, callback)
+    }
+}
+
+/// Allocate a runtime length zeroed byte buffer on the stack if `threshold` allows it (or on the heap if not), call `callback` with this buffer, and then deallocate the buffer.
+///
+/// See `alloca_zeroed()`.
+#[inline] pub fn alloca_zeroed_or_heap<T, F>(size: usize, threshold: Threshold, callback: F) -> T
+where F: FnOnce(&mut [u8]) -> T
+{
+    if threshold.allows(size) {
+	alloca_zeroed(size, callback)
+    } else {
+	let mut buf = vec![0u8; size];
+	callback(&mut buf[..])
+    }
+}
+
+/// Allocate a runtime length slice of uninitialised `T` on the stack if `threshold` allows it (or on the heap if not), call `callback` with this buffer, and then deallocate the buffer.
+///
+/// See `stackalloc_uninit()`.
+///
+/// # Panics
+/// If the size of the buffer overflows (see `Layout::array()`.)
+#[inline] pub fn stackalloc_uninit_or_heap<T, U, F>(size: usize, threshold: Threshold, callback: F) -> U
+where F: FnOnce(&mut [MaybeUninit<T>]) -> U
+{
+    if threshold.allows(padded_size(array_layout::<T>(size))) {
+	stackalloc_uninit(size, callback)
+    } else {
+	heap_uninit(size, callback)
+    }
+}
+
+/// Allocate a runtime length slice of `T` on the stack if `threshold` allows it (or on the heap if not), fill it by calling `init_with`, call `callback` with this buffer, and then drop and deallocate the buffer.
+///
+/// See `stackalloc_with()`.
+#[inline] pub fn stackalloc_with_or_heap<T, U, F, I>(size: usize, threshold: Threshold, init_with: I, callback: F) -> U
+where F: FnOnce(&mut [T]) -> U,
+      I: FnMut() -> T
+{
+    stackalloc_uninit_or_heap(size, threshold, move |buf| init_with_in(buf, init_with, callback))
+}
+
+/// Allocate a runtime length slice of `T` on the stack if `threshold` allows it (or on the heap if not), fill it by cloning `init`, call `callback` with this buffer, and then drop and deallocate the buffer.
+///
+/// See `stackalloc()`.
+#[inline] pub fn stackalloc_or_heap<T, U, F>(size: usize, threshold: Threshold, init: T, callback: F) -> U
+where F: FnOnce(&mut [T]) -> U,
+      T: Clone
+{
+    stackalloc_with_or_heap(size, threshold, move || init.clone(), callback)
+}
+
+/// Allocate a runtime length slice of `T` on the stack if `threshold` allows it (or on the heap if not), fill it by calling `T::default()`, call `callback` with this buffer, and then drop and deallocate the buffer.
+///
+/// See `stackalloc_with_default()`.
+#[inline] pub fn stackalloc_with_default_or_heap<T, U, F>(size: usize, threshold: Threshold, callback: F) -> U
+where F: FnOnce(&mut [T]) -> U,
+      T: Default
+{
+    stackalloc_with_or_heap(size, threshold, T::default, callback)
+}
+
+/// Collect an iterator into a buffer of up to `size` elements on the stack if `threshold` allows it (or on the heap if not), call `callback` with this buffer, and then drop and deallocate the buffer.
+///
+/// See `stackalloc_with_iter()`.
+#[inline] pub fn stackalloc_with_iter_or_heap<I, T, U, F>(size: usize, threshold: Threshold, iter: I, callback: F) -> U
+where F: FnOnce(&mut [T]) -> U,
+      I: IntoIterator<Item = T>,
+{
+    stackalloc_uninit_or_heap(size, threshold, move |buf| init_from_iter_in(buf, iter, callback))
+}
@@ -1,20 +1,148 @@
 use core::ffi::c_void;
 
+#[cfg(not(all(unwind_trampoline, panic = "unwind")))]
 pub type CallbackRaw = unsafe extern "C" fn (ptr: *mut c_void, data: *mut c_void)->();
+/// Without `std` on nightly, panics in the callback are not caught, so they must be allowed to unwind through the trampoline.
+#[cfg(all(unwind_trampoline, panic = "unwind"))]
+pub type CallbackRaw = unsafe extern "C-unwind" fn (ptr: *mut c_void, data: *mut c_void)->();
 
-extern "C" {
-    fn _alloca_trampoline(size: usize, cb: Option<CallbackRaw>, data: *mut c_void);
+/// Declare `_alloca_trampoline` with the same ABI as `CallbackRaw`, since `cb` may unwind through it.
+macro_rules! extern_trampoline {
+    ($abi:literal) => {
+	#[cfg(not(asm_trampoline))]
+	extern $abi {
+	    fn _alloca_trampoline(size: usize, cb: Option<CallbackRaw>, data: *mut c_void);
+	}
+
+	#[cfg(asm_trampoline)]
+	extern $abi {
+	    #[link_name = "_stackalloc_asm_trampoline"]
+	    fn _alloca_trampoline(size: usize, cb: Option<CallbackRaw>, data: *mut c_void);
+	}
+    };
+}
+
+#[cfg(not(all(unwind_trampoline, panic = "unwind")))]
+extern_trampoline!("C");
+#[cfg(all(unwind_trampoline, panic = "unwind"))]
+extern_trampoline!("C-unwind");
+
+/// Define the `_stackalloc_asm_trampoline` function with the same signature and behaviour as `_alloca_trampoline` in `alloca_trampoline_.c`, from the body of the function in assembly.
+#[cfg(asm_trampoline)]
+macro_rules! asm_trampoline {
+    ($($body:literal),* $(,)?) => {
+	#[cfg(not(target_vendor = "apple"))]
+	core::arch::global_asm!(
+	    ".text",
+	    ".globl _stackalloc_asm_trampoline",
+	    ".hidden _stackalloc_asm_trampoline",
+	    ".type _stackalloc_asm_trampoline,@function",
+	    ".p2align 4",
+	    "_stackalloc_asm_trampoline:",
+	    $($body,)*
+	    ".size _stackalloc_asm_trampoline, .-_stackalloc_asm_trampoline",
+	);
+	// Mach-O prefixes C symbols with an underscore.
+	#[cfg(target_vendor = "apple")]
+	core::arch::global_asm!(
+	    ".text",
+	    ".globl __stackalloc_asm_trampoline",
+	    ".private_extern __stackalloc_asm_trampoline",
+	    ".p2align 4",
+	    "__stackalloc_asm_trampoline:",
+	    $($body,)*
+	);
+    };
 }
 
-/// Call the `_alloca_trampoline` C function.
+// Arguments: `rdi` = size, `rsi` = cb, `rdx` = data.
+//
+// Each page between the old and new stack pointer is touched in order (like `__chkstk`), and then the new stack pointer itself, which may be up to a page below the last probe. So we always hit the guard page instead of jumping over it.
+#[cfg(all(asm_trampoline, target_arch = "x86_64"))]
+asm_trampoline!(
+    ".cfi_startproc",
+    "push rbp",
+    ".cfi_def_cfa_offset 16",
+    ".cfi_offset rbp, -16",
+    "mov rbp, rsp",
+    ".cfi_def_cfa_register rbp",
+    "mov rax, rsp",
+    "sub rax, rdi",
+    "and rax, -16",
+    "mov rcx, rsp",
+    "2:",
+    "sub rcx, 4096",
+    "cmp rcx, rax",
+    "jb 3f",
+    "test byte ptr [rcx], al",
+    "jmp 2b",
+    "3:",
+    "test byte ptr [rax], al",
+    "mov rsp, rax",
+    "mov rdi, rsp",
+    "mov rax, rsi",
+    "mov rsi, rdx",
+    "call rax",
+    "mov rsp, rbp",
+    "pop rbp",
+    ".cfi_def_cfa rsp, 8",
+    "ret",
+    ".cfi_endproc",
+);
+
+// Arguments: `x0` = size, `x1` = cb, `x2` = data.
+//
+// See above for the probing. 4096 is the smallest page size on aarch64, so this is correct for larger pages too.
+#[cfg(all(asm_trampoline, target_arch = "aarch64"))]
+asm_trampoline!(
+    ".cfi_startproc",
+    "stp x29, x30, [sp, #-16]!",
+    ".cfi_def_cfa_offset 16",
+    ".cfi_offset x30, -8",
+    ".cfi_offset x29, -16",
+    "mov x29, sp",
+    ".cfi_def_cfa x29, 16",
+    "mov x9, sp",
+    "sub x10, x9, x0",
+    "and x10, x10, #-16",
+    "2:",
+    "sub x9, x9, #4096",
+    "cmp x9, x10",
+    "b.lo 3f",
+    "ldrb w11, [x9]",
+    "b 2b",
+    "3:",
+    "ldrb w11, [x10]",
+    "mov sp, x10",
+    "mov x0, sp",
+    "mov x9, x1",
+    "mov x1, x2",
+    "blr x9",
+    "mov sp, x29",
+    ".cfi_def_cfa sp, 16",
+    "ldp x29, x30, [sp], #16",
+    ".cfi_def_cfa_offset 0",
+    ".cfi_restore x30",
+    ".cfi_restore x29",
+    "ret",
+    ".cfi_endproc",
+);
+
+/// Call the `_alloca_trampoline` C function (or its assembly equivalent with the `asm_trampoline` feature.)
 ///
 /// # Safety requirements & guarantees
 /// * `size` should be small enough to not overflow the stack. A size of 0 is allowed.
-/// * `cb` **must** catch any unwinds.
+/// * `cb` **must** catch any unwinds, unless it is `C-unwind` (with `no_std` and `panic = "unwind"` on nightly.)
 /// * `data` can be `null`, it is passed as the 2nd argument to `cb` as-is.
 /// * The first argument to `cb` is guaranteed to be a non-aliased, properly aligned, and non-null pointer with `size` read+writable memory. If `size` is 0, it may dangle.
 /// * `cb` is guaranteed to be called unless allocating `size` bytes on the stack causes a stack overflow, in which case the program will terminate.
-/// * The data pointed to by `ptr` is guaranteed to be popped from the stack once this function returns (even in the case of a `longjmp`, but `panic!()` within the callback is still undefined behaviour).
+/// * The data pointed to by `ptr` is guaranteed to be popped from the stack once this function returns (even in the case of a `longjmp`.)
+///
+/// # Unwinding
+/// * With `std`, `alloca()` catches panics inside `cb` and resumes them after this function returns, so nothing unwinds through the trampoline. `cb` is `extern "C"`, so an unwind escaping it aborts the process.
+/// * With `panic = "abort"` (including the `no_unwind_protection` feature), nothing can unwind at all.
+/// * With `no_std` and `panic = "unwind"` on nightly, `cb` is `C-unwind` and panics unwind through the trampoline, which pops the stack allocation. Only in this configuration is the C trampoline built with `-fexceptions`, so it has unwind tables. The assembly trampoline always describes its frame with CFI directives.
+/// * With `no_std` and `panic = "unwind"` on other toolchains, the build fails (see `lib.rs`.)
 // Never inline this, in case LTO inlines the call to `_alloca_trampoline`, we always want this function to pop the alloca'd memory.
 // (NOTE: Test to see if this can ever happen. If it can't, the change this to `inline(always)` or remove the `inline` attribute.)
 #[inline(never)] pub unsafe fn alloca_trampoline(size: usize, cb: CallbackRaw, data: *mut c_void)
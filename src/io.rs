@@ -0,0 +1,303 @@
+//! `std::io` helpers that buffer through a stack allocated buffer instead of a heap allocated one.
+//!
+//! Only available with `std`.
+//!
+//! # Example
+//! ```
+//! # use stackalloc::io::{with_stack_bufreader, with_stack_bufwriter};
+//! use std::io::{BufRead, Write};
+//!
+//! let input = "first\nsecond\nthird\n";
+//! let mut output = Vec::new();
+//! with_stack_bufwriter(&mut output, 256, |writer| {
+//!   with_stack_bufreader(input.as_bytes(), 64, |reader| {
+//!     for line in reader.lines() {
+//!       writeln!(writer, "{}", line?.to_uppercase())?;
+//!     }
+//!     Ok(())
+//!   })
+//! }).unwrap();
+//! assert_eq!(output, b"FIRST\nSECOND\nTHIRD\n");
+//! ```
+use super::*;
+use core::{
+    fmt,
+    ops::ControlFlow,
+};
+use std::io::{
+    self,
+    BufRead,
+    Read,
+    Write,
+};
+
+/// Copy the whole contents of `reader` into `writer` through a stack allocated buffer of `size` bytes, and return the number of bytes copied.
+///
+/// This is like `std::io::copy()`, which uses a fixed size buffer. Reads that are interrupted are retried.
+///
+/// See `read_chunks()`.
+///
+/// # Panics
+/// If `size` is 0.
+pub fn copy_with_stack_buffer<R, W>(reader: &mut R, writer: &mut W, size: usize) -> io::Result<u64>
+where R: Read + ?Sized,
+      W: Write + ?Sized,
+{
+    let mut copied = 0u64;
+    match read_chunks(reader, size, |bytes| match writer.write_all(bytes) {
+	Ok(()) => {
+	    copied += bytes.len() as u64;
+	    ControlFlow::Continue(())
+	},
+	Err(err) => ControlFlow::Break(err),
+    })? {
+	ControlFlow::Continue(()) => Ok(copied),
+	ControlFlow::Break(err) => Err(err),
+    }
+}
+
+/// Read exactly `len` bytes from `reader` into a stack allocated buffer, call `callback` with this buffer, and then deallocate the buffer.
+///
+/// `callback` is not called if the bytes cannot be read (see `Read::read_exact()`.)
+///
+/// See `alloca_zeroed()`.
+pub fn read_exact_into<R, U, F>(reader: &mut R, len: usize, callback: F) -> io::Result<U>
+where F: FnOnce(&mut [u8]) -> U,
+      R: Read + ?Sized,
+{
+    alloca_zeroed(len, move |buf| {
+	reader.read_exact(buf)?;
+	Ok(callback(buf))
+    })
+}
+
+/// A buffered reader over a stack allocated buffer, created by `with_stack_bufreader()`.
+///
+/// This works like `std::io::BufReader`.
+pub struct StackBufReader<'a, R>
+{
+    inner: R,
+    buf: &'a mut [u8],
+    pos: usize,
+    filled: usize,
+}
+
+impl<'a, R> StackBufReader<'a, R>
+{
+    /// A reference to the underlying reader.
+    #[inline] pub fn get_ref(&self) -> &R
+    {
+	&self.inner
+    }
+
+    /// A mutable reference to the underlying reader.
+    ///
+    /// Reading from it directly skips the data that is buffered.
+    #[inline] pub fn get_mut(&mut self) -> &mut R
+    {
+	&mut self.inner
+    }
+
+    /// The data that is currently buffered, without filling the buffer if it is empty.
+    #[inline] pub fn buffer(&self) -> &[u8]
+    {
+	&self.buf[self.pos..self.filled]
+    }
+
+    /// The size of the buffer.
+    #[inline] pub fn capacity(&self) -> usize
+    {
+	self.buf.len()
+    }
+}
+
+impl<'a, R: Read> Read for StackBufReader<'a, R>
+{
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize>
+    {
+	// Large reads with nothing buffered skip the buffer.
+	if self.pos == self.filled && out.len() >= self.buf.len() {
+	    return self.inner.read(out);
+	}
+	let read = self.fill_buf()?.read(out)?;
+	self.consume(read);
+	Ok(read)
+    }
+}
+
+impl<'a, R: Read> BufRead for StackBufReader<'a, R>
+{
+    fn fill_buf(&mut self) -> io::Result<&[u8]>
+    {
+	if self.pos == self.filled {
+	    self.filled = self.inner.read(self.buf)?;
+	    self.pos = 0;
+	}
+	Ok(self.buffer())
+    }
+
+    #[inline] fn consume(&mut self, amt: usize)
+    {
+	self.pos = (self.pos + amt).min(self.filled);
+    }
+}
+
+impl<'a, R: fmt::Debug> fmt::Debug for StackBufReader<'a, R>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+	f.debug_struct("StackBufReader")
+	    .field("reader", &self.inner)
+	    .field("buffer", &format_args!("{}/{}", self.filled - self.pos, self.capacity()))
+	    .finish()
+    }
+}
+
+/// Allocate a runtime length buffer of `capacity` bytes on the stack, call `callback` with a `StackBufReader` reading from `reader` through this buffer, and then deallocate the buffer.
+///
+/// Any data left in the buffer when `callback` returns is discarded. Pass `&mut reader` to keep using `reader` afterwards.
+///
+/// See `alloca_zeroed()`.
+///
+/// # Panics
+/// If `capacity` is 0.
+pub fn with_stack_bufreader<R, U, F>(reader: R, capacity: usize, callback: F) -> U
+where F: FnOnce(&mut StackBufReader<'_, R>) -> U,
+      R: Read,
+{
+    assert!(capacity != 0, "capacity must not be zero");
+    alloca_zeroed(capacity, move |buf| {
+	callback(&mut StackBufReader {
+	    inner: reader,
+	    buf,
+	    pos: 0,
+	    filled: 0,
+	})
+    })
+}
+
+/// A buffered writer over a stack allocated buffer, created by `with_stack_bufwriter()`.
+///
+/// This works like `std::io::BufWriter`.
+pub struct StackBufWriter<'a, W: Write>
+{
+    inner: W,
+    buf: &'a mut [MaybeUninit<u8>],
+    len: usize,
+}
+
+impl<'a, W: Write> StackBufWriter<'a, W>
+{
+    /// A reference to the underlying writer.
+    #[inline] pub fn get_ref(&self) -> &W
+    {
+	&self.inner
+    }
+
+    /// A mutable reference to the underlying writer.
+    ///
+    /// Writing to it directly writes before the data that is buffered.
+    #[inline] pub fn get_mut(&mut self) -> &mut W
+    {
+	&mut self.inner
+    }
+
+    /// The data that is currently buffered.
+    #[inline] pub fn buffer(&self) -> &[u8]
+    {
+	// SAFETY: The first `len` bytes have been written to.
+	unsafe { slice_assume_init(&self.buf[..self.len]) }
+    }
+
+    /// The size of the buffer.
+    #[inline] pub fn capacity(&self) -> usize
+    {
+	self.buf.len()
+    }
+
+    /// Write all of the buffered data to the underlying writer, without flushing it.
+    ///
+    /// If this fails, the data that was not written stays in the buffer.
+    fn flush_buf(&mut self) -> io::Result<()>
+    {
+	let mut written = 0;
+	let ret = loop {
+	    if written == self.len {
+		break Ok(());
+	    }
+	    // SAFETY: The first `len` bytes have been written to.
+	    match self.inner.write(unsafe { slice_assume_init(&self.buf[written..self.len]) }) {
+		Ok(0) => break Err(io::ErrorKind::WriteZero.into()),
+		Ok(n) => written += n,
+		Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+		Err(err) => break Err(err),
+	    }
+	};
+	self.buf.copy_within(written..self.len, 0);
+	self.len -= written;
+	ret
+    }
+}
+
+impl<'a, W: Write> Write for StackBufWriter<'a, W>
+{
+    fn write(&mut self, data: &[u8]) -> io::Result<usize>
+    {
+	if self.len + data.len() > self.buf.len() {
+	    self.flush_buf()?;
+	}
+	// Writes that do not fit in the buffer skip it.
+	if data.len() >= self.buf.len() {
+	    return self.inner.write(data);
+	}
+	// SAFETY: `&[u8]` and `&[MaybeUninit<u8>]` have the same layout, and `data` fits in the rest of the buffer since we flushed it above.
+	let data = unsafe { &*(data as *const [u8] as *const [MaybeUninit<u8>]) };
+	self.buf[self.len..self.len + data.len()].copy_from_slice(data);
+	self.len += data.len();
+	Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()>
+    {
+	self.flush_buf()?;
+	self.inner.flush()
+    }
+}
+
+impl<'a, W: Write + fmt::Debug> fmt::Debug for StackBufWriter<'a, W>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+	f.debug_struct("StackBufWriter")
+	    .field("writer", &self.inner)
+	    .field("buffer", &format_args!("{}/{}", self.len, self.capacity()))
+	    .finish()
+    }
+}
+
+/// Allocate a runtime length buffer of `capacity` bytes on the stack, call `callback` with a `StackBufWriter` writing to `writer` through this buffer, flush it, and then deallocate the buffer.
+///
+/// The writer is flushed even if `callback` returns an error, in which case that error is returned instead of any error flushing. If `callback` panics, the buffered data is discarded.
+/// Pass `&mut writer` to keep using `writer` afterwards.
+///
+/// See `alloca()`.
+///
+/// # Panics
+/// If `capacity` is 0.
+pub fn with_stack_bufwriter<W, U, F>(writer: W, capacity: usize, callback: F) -> io::Result<U>
+where F: FnOnce(&mut StackBufWriter<'_, W>) -> io::Result<U>,
+      W: Write,
+{
+    assert!(capacity != 0, "capacity must not be zero");
+    alloca(capacity, move |buf| {
+	let mut writer = StackBufWriter {
+	    inner: writer,
+	    buf,
+	    len: 0,
+	};
+	let ret = callback(&mut writer);
+	let flushed = writer.flush();
+	let ret = ret?;
+	flushed.map(move |_| ret)
+    })
+}
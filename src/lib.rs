@@ -26,6 +26,7 @@
 //!   })
 //! }
 //! ```
+//! The `io` module provides this as `io::copy_with_stack_buffer()`, along with stack buffered readers and writers.
 //! ## Arbitrary types
 //! Allocating a slice of any type on the stack.
 //! ```
@@ -82,18 +83,28 @@
 //! * test tests::bench::vec_of_zeroed_bytes_known          ... bench:          36 ns/iter (+/- 2)
 //! * test tests::bench::vec_of_zeroed_bytes_unknown        ... bench:          37 ns/iter (+/- 0)
 //!
+//! When a small upper bound for the size is known, the `_bounded` functions (see `bounded`) avoid the trampoline call entirely for sizes within it.
+//! Compare the `tests::bench::bounded_*_small` benchmarks with the `tests::bench::stackalloc_*_small` ones (`cargo +nightly bench`) to see the difference on your machine.
+//!
 //! # License
 //! MIT licensed
 
 #![cfg_attr(nightly, feature(test))] 
+#![cfg_attr(feature = "coerce_unsized", feature(coerce_unsized, unsize))]
+#![cfg_attr(all(nightly, feature = "allocator_api"), feature(allocator_api))]
 
 #![allow(dead_code)]
+// The original tests predate these lints of newer clippy versions.
+#![cfg_attr(test, allow(clippy::missing_transmute_annotations, clippy::manual_str_repeat, clippy::manual_repeat_n))]
 
 
 #![cfg_attr(all(feature = "no_std", not(test)), no_std)]
-#![cfg_attr(all(feature = "no_std", not(feature="no_unwind_protection")), feature(core_intrinsics))]
 
-// NOTE: This feature `no_unwind_protection` doesn't actually exist at the moment; since a binary crate built with #![no_std] will not be using a stable compiler toolchain. It was just for testing.
+#[cfg(all(feature = "no_unwind_protection", panic = "unwind"))]
+compile_error!("The `no_unwind_protection` feature can only be used with `panic = \"abort\"`, panics must not unwind through the trampoline without protection.");
+// Without `std`, panics are only allowed to unwind through the trampoline on nightly (see `build.rs`.) Tests are built with `std` regardless.
+#[cfg(all(feature = "no_std", panic = "unwind", not(unwind_trampoline), not(test)))]
+compile_error!("The `no_std` feature can only be used with `panic = \"abort\"` on stable and beta toolchains, panics cannot be caught without `std`.");
 
 #[cfg(all(nightly, test))] extern crate test;
 
@@ -111,6 +122,10 @@ use core::{
     slice,
     ffi::c_void,
     ptr,
+    alloc::{
+	Layout,
+	LayoutError,
+    },
 };
 
 
@@ -118,6 +133,108 @@ use core::{
 pub mod avec;
 #[cfg(not(feature = "no_std"))]
 pub use avec::AVec;
+#[cfg(not(feature = "no_std"))]
+pub mod string;
+#[cfg(not(feature = "no_std"))]
+pub use string::StackStr;
+
+#[cfg(all(feature = "stats", not(feature = "no_std")))]
+pub mod stats;
+
+#[cfg(not(feature = "no_std"))]
+pub mod budget;
+
+#[cfg(not(feature = "no_std"))]
+pub mod io;
+
+pub mod stack;
+pub use stack::{
+    StackExhausted,
+    TryAllocError,
+};
+
+#[cfg(not(feature = "no_std"))]
+pub mod fallback;
+#[cfg(not(feature = "no_std"))]
+pub use fallback::{
+    Threshold,
+    alloca_or_heap,
+    alloca_zeroed_or_heap,
+    stackalloc_uninit_or_heap,
+    stackalloc_with_or_heap,
+    stackalloc_or_heap,
+    stackalloc_with_default_or_heap,
+    stackalloc_with_iter_or_heap,
+};
+
+pub mod cstr;
+pub use cstr::{
+    InteriorNulError,
+    with_cstr,
+};
+#[cfg(all(unix, not(feature = "no_std")))]
+pub use cstr::{
+    with_os_cstr,
+    with_path_cstr,
+};
+
+pub mod tuple;
+pub use tuple::{
+    stackalloc_tuple,
+    stackalloc_tuple_uninit,
+};
+
+pub mod stackbox;
+pub use stackbox::{
+    StackBox,
+    stackalloc_box,
+    stackalloc_dyn,
+};
+
+mod bump;
+
+#[cfg(not(feature = "no_std"))]
+pub mod arena;
+#[cfg(not(feature = "no_std"))]
+pub use arena::{
+    StackArena,
+    ArenaExhausted,
+    with_stack_arena,
+};
+
+pub mod allocator;
+pub use allocator::{
+    StackAllocator,
+    with_stack_allocator,
+};
+
+pub mod secure;
+pub use secure::{
+    alloca_secure,
+    stackalloc_secure_with,
+    stackalloc_secure,
+};
+
+pub mod collect;
+pub use collect::stackalloc_collect;
+#[cfg(not(feature = "no_std"))]
+pub use collect::stackalloc_collect_or_heap;
+
+pub mod chunks;
+pub use chunks::chunks;
+#[cfg(not(feature = "no_std"))]
+pub use chunks::read_chunks;
+
+pub mod bounded;
+pub use bounded::{
+    alloca_bounded,
+    alloca_zeroed_bounded,
+    stackalloc_uninit_bounded,
+    stackalloc_with_bounded,
+    stackalloc_bounded,
+    stackalloc_with_default_bounded,
+    stackalloc_with_iter_bounded,
+};
 
 mod ffi;
 
@@ -150,9 +267,17 @@ mod ffi;
 /// ## Cleanup
 /// Immediately after the closure exits, the stack pointer is reset, effectively freeing the buffer. The pointer used for the creation of the slice is invalidated as soon as the closure exits. But in the absense of `unsafe` inside the closure, it isn't possible to keep this pointer around after the frame is destroyed.
 ///
+/// ## Budget
+/// If allocating `size` bytes would exceed the current thread's stack budget (see `budget`), the buffer is allocated on the heap instead.
+///
 /// ## Panics
 /// The closure can panic and it will be caught and propagated after exiting the FFI boundary and resetting the stack pointer.
 ///
+/// With the `no_std` feature, panics cannot be caught, so the crate must be built with `panic = "abort"` (the build fails otherwise.)
+/// On nightly toolchains `panic = "unwind"` is also allowed: the panic unwinds through the trampoline itself (which is compiled with unwind tables for this), so the original payload still reaches the caller.
+///
+/// With the `no_unwind_protection` feature (which requires `panic = "abort"`), panics are not caught in `std` builds either, removing the overhead of `catch_unwind()`.
+///
 /// # Internals
 /// This function creates a shim stack frame (by way of a small FFI function) and uses the same mechanism as a C VLA to extend the stack pointer by the size provided (plus alignment). Then, this pointer is passed to the provided closure, and after the closure returns to the shim stack frame, the stack pointer is reset to the base of the caller of this function.
 ///
@@ -162,6 +287,11 @@ mod ffi;
 pub fn alloca<T, F>(size: usize, callback: F) -> T
 where F: FnOnce(&mut [MaybeUninit<u8>]) -> T
 {
+    #[cfg(not(feature = "no_std"))]
+    if !budget::allows(size) {
+	return fallback::heap_uninit(size, callback);
+    }
+
     let mut callback = ManuallyDrop::new(callback);
     let mut rval = MaybeUninit::uninit();
 
@@ -170,14 +300,14 @@ where F: FnOnce(&mut [MaybeUninit<u8>]) -> T
 	    let slice = slice::from_raw_parts_mut(allocad_ptr as *mut MaybeUninit<u8>, size);
 	    let callback = ManuallyDrop::take(&mut callback);
 
-        #[cfg(feature = "no_std")]
+	    #[cfg(any(feature = "no_std", feature = "no_unwind_protection"))]
 	    {
-            rval = MaybeUninit::new(catch_unwind(move||{callback(slice)}));
-        }
-        #[cfg(not(feature = "no_std"))]
-        {
-            rval = MaybeUninit::new(std::panic::catch_unwind(AssertUnwindSafe(move || callback(slice))));
-        }
+		rval = MaybeUninit::new(callback(slice));
+	    }
+	    #[cfg(not(any(feature = "no_std", feature = "no_unwind_protection")))]
+	    {
+		rval = MaybeUninit::new(std::panic::catch_unwind(AssertUnwindSafe(move || callback(slice))));
+	    }
 	}
     };
 
@@ -185,78 +315,39 @@ where F: FnOnce(&mut [MaybeUninit<u8>]) -> T
     #[inline(always)] fn create_trampoline<F>(_: &F) -> ffi::CallbackRaw
     where F: FnMut(*mut c_void)
     {
+	#[cfg(not(all(unwind_trampoline, panic = "unwind")))]
 	unsafe extern "C" fn trampoline<F: FnMut(*mut c_void)>(ptr: *mut c_void, data: *mut c_void)
 	{
 	    (&mut *(data as *mut F))(ptr);
 	}
+	// Without `std` we cannot catch the panic, so on nightly it unwinds through the trampoline instead.
+	#[cfg(all(unwind_trampoline, panic = "unwind"))]
+	unsafe extern "C-unwind" fn trampoline<F: FnMut(*mut c_void)>(ptr: *mut c_void, data: *mut c_void)
+	{
+	    (&mut *(data as *mut F))(ptr);
+	}
 
 	trampoline::<F>
     }
 
+    #[cfg(all(feature = "stats", not(feature = "no_std")))]
+    let _record = stats::Record::new(size);
+    #[cfg(not(feature = "no_std"))]
+    let _charge = budget::Charge::new(size);
+
     let rval = unsafe {
         ffi::alloca_trampoline(size, create_trampoline(&callback), &mut callback as *mut _ as *mut c_void);
         rval.assume_init()
     };
     
-    #[cfg(not(feature = "no_std"))]
+    #[cfg(not(any(feature = "no_std", feature = "no_unwind_protection")))]
     match rval
     {
         Ok(v) => v,
         Err(pan) => std::panic::resume_unwind(pan),
     }
-    #[cfg(feature = "no_std")]
-    return match rval{
-        Ok(v) => v,
-        Err(()) => core::panic!(),
-    }
-}
-
-
-
-
-#[cfg(all(feature = "no_std", feature = "no_unwind_protection"))] 
-unsafe fn catch_unwind<R, F: FnOnce() -> R>(f: F) -> Result<R, ()> {
-    // Catching unwinds disabled for this build for now since it requires core intrinsics.
-    Ok(f())
-}
-
-#[cfg(all(feature = "no_std", not(feature = "no_unwind_protection")))]
-unsafe fn catch_unwind<R, F: FnOnce() -> R>(f: F) -> Result<R, ()>{
-    
-    union Data<F, R> {
-        f: ManuallyDrop<F>,
-        r: ManuallyDrop<R>,
-        p: (),
-    }
-    
-    #[inline]
-    fn do_call<F: FnOnce() -> R, R>(data: *mut u8) {
-        unsafe {
-            let data = data as *mut Data<F, R>;
-            let data = &mut (*data);
-            let f = ManuallyDrop::take(&mut data.f);
-            data.r = ManuallyDrop::new(f());
-        }
-    }
-
-    #[inline]
-    fn do_catch<F: FnOnce() -> R, R>(data: *mut u8, _payload: *mut u8) {
-        unsafe {
-            let data = data as *mut Data<F, R>;
-            let data = &mut (*data);
-            data.p = ()
-        }
-    }
-
-    let mut data = Data { f: ManuallyDrop::new(f) };
-    let data_ptr = &mut data as *mut _ as *mut u8;
-
-    
-    if core::intrinsics::r#try(do_call::<F, R>, data_ptr, do_catch::<F, R>) == 0{
-        Result::Ok(ManuallyDrop::into_inner(data.r))
-    }else{
-        Result::Err(())
-    }
+    #[cfg(any(feature = "no_std", feature = "no_unwind_protection"))]
+    return rval;
 }
 
 /// A module of helper functions for slice memory manipulation
@@ -264,10 +355,13 @@ unsafe fn catch_unwind<R, F: FnOnce() -> R>(f: F) -> Result<R, ()>{
 /// These are mostly re-implementations of unstable corelib functions in stable Rust.
 pub mod helpers {
     use super::*;
-    #[inline(always)] pub(crate) fn align_buffer_to<T>(ptr: *mut u8) -> *mut T
+    /// The number of bytes needed to be added to `ptr` to align it to `align`, which must be a power of 2.
+    ///
+    /// This is 0 if `ptr` is already aligned.
+    #[inline(always)] pub(crate) fn align_padding(ptr: *const u8, align: usize) -> usize
     {
-	use core::mem::align_of;
-	((ptr as usize) + align_of::<T>() - (ptr as usize) % align_of::<T>()) as *mut T
+	debug_assert!(align.is_power_of_two());
+	(ptr as usize).wrapping_neg() & (align - 1)
     }
 
     /// Convert a slice of `MaybeUninit<T>` to `T`.
@@ -295,6 +389,58 @@ pub mod helpers {
 
 use helpers::*;
 
+/// The layout of a buffer of `size` elements of `T`.
+///
+/// # Panics
+/// If the size of the buffer overflows.
+#[inline(always)] pub(crate) fn array_layout<T>(size: usize) -> Layout
+{
+    #[cold]
+    #[inline(never)]
+    fn overflow<T>(size: usize) -> !
+    {
+	panic!("stackalloc: buffer of {} elements of `{}` is too large", size, core::any::type_name::<T>())
+    }
+
+    Layout::array::<T>(size).unwrap_or_else(|_| overflow::<T>(size))
+}
+
+/// The number of bytes `alloca_aligned()` allocates for `layout`.
+#[inline(always)] pub(crate) fn padded_size(layout: Layout) -> usize
+{
+    // `Layout` guarantees its size rounded up to its alignment does not overflow `isize`.
+    layout.size() + (layout.align() - 1)
+}
+
+/// Allocate a runtime length uninitialised byte buffer aligned to `align` on the stack, call `callback` with this buffer, and then deallocate the buffer.
+///
+/// This allows alignments larger than what the stack usually provides, e.g. to cache lines or pages.
+/// Up to `align - 1` extra bytes are allocated to pad the start of the buffer to the requested alignment.
+///
+/// See `alloca()`.
+///
+/// # Panics
+/// If `align` is not a power of 2, or the size of the buffer plus its padding overflows `usize`.
+///
+/// # Example
+/// ```
+/// # use stackalloc::alloca_aligned;
+/// alloca_aligned(1024, 64, |buf| {
+///  assert_eq!(buf.len(), 1024);
+///  assert_eq!(buf.as_ptr() as usize % 64, 0);
+/// });
+/// ```
+#[inline] pub fn alloca_aligned<T, F>(size: usize, align: usize, callback: F) -> T
+where F: FnOnce(&mut [MaybeUninit<u8>]) -> T
+{
+    assert!(align.is_power_of_two(), "alloca_aligned: alignment {} is not a power of 2", align);
+    let size_padded = size.checked_add(align - 1).expect("alloca_aligned: allocation size overflow");
+    alloca(size_padded, move |buf| {
+	let padding = align_padding(buf.as_ptr() as *const u8, align);
+	callback(&mut buf[padding..(padding + size)])
+    })
+}
+
 /// Allocate a runtime length zeroed byte buffer on the stack, call `callback` with this buffer, and then deallocate the buffer.
 ///
 /// See `alloca()`.
@@ -315,16 +461,24 @@ where F: FnOnce(&mut [u8]) -> T
 ///
 /// The slice is aligned to type `T`.
 ///
+/// If `T` is zero-sized, nothing is allocated and `callback` is called with a dangling slice of length `size`.
+///
 /// See `alloca()`.
+///
+/// # Panics
+/// If the size of the buffer overflows (see `Layout::array()`.) Use `try_stackalloc_uninit()` to handle this case instead.
 #[inline] pub fn stackalloc_uninit<T, U, F>(size: usize, callback: F) -> U
 where F: FnOnce(&mut [MaybeUninit<T>]) -> U
 {
-    let size_bytes = (core::mem::size_of::<T>() * size) + core::mem::align_of::<T>();
-    alloca(size_bytes, move |buf| {
-	let abuf = align_buffer_to::<MaybeUninit<T>>(buf.as_mut_ptr() as *mut u8);
-	debug_assert!(buf.as_ptr_range().contains(&(abuf as *const _ as *const MaybeUninit<u8>)));
+    if core::mem::size_of::<T>() == 0 {
+	// SAFETY: A slice of a zero-sized type of any length only needs a non-null, aligned pointer.
+	return callback(unsafe { slice::from_raw_parts_mut(ptr::NonNull::dangling().as_ptr(), size) });
+    }
+    let layout = array_layout::<T>(size);
+    alloca_aligned(layout.size(), layout.align(), move |buf| {
+	// SAFETY: `buf` is aligned to `T` and is large enough to hold `size` elements of it.
 	unsafe {
-	    callback(slice::from_raw_parts_mut(abuf, size))
+	    callback(slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut MaybeUninit<T>, size))
 	}
     })
 }
@@ -334,24 +488,51 @@ where F: FnOnce(&mut [MaybeUninit<T>]) -> U
 /// The slice is aligned to type `T`.
 ///
 /// See `alloca()`.
-#[inline] pub fn stackalloc_with<T, U, F, I>(size: usize, mut init_with: I, callback: F) -> U
+#[inline] pub fn stackalloc_with<T, U, F, I>(size: usize, init_with: I, callback: F) -> U
 where F: FnOnce(&mut [T]) -> U,
       I: FnMut() -> T
 {
-    stackalloc_uninit(size, move |buf| {
-	buf.fill_with(move || MaybeUninit::new(init_with()));
-	// SAFETY: We have initialised the buffer above
-	let buf = unsafe { slice_assume_init_mut(buf) };
-	let ret = callback(buf);
-	if mem::needs_drop::<T>()
-	{
-	    // SAFETY: We have initialised the buffer above
-	    unsafe {
-		ptr::drop_in_place(buf as *mut _);
-	    }
+    stackalloc_uninit(size, move |buf| init_with_in(buf, init_with, callback))
+}
+
+/// Drops the first `len` elements at `ptr` when dropped, so the initialised part of a buffer is dropped even if initialising the rest of it (or using it) panics.
+pub(crate) struct InitGuard<T>
+{
+    pub(crate) ptr: *mut T,
+    pub(crate) len: usize,
+}
+
+impl<T> Drop for InitGuard<T>
+{
+    #[inline] fn drop(&mut self)
+    {
+	// SAFETY: The first `len` elements at `ptr` have been initialised, and nothing else drops them.
+	unsafe {
+	    ptr::drop_in_place(ptr::slice_from_raw_parts_mut(self.ptr, self.len));
 	}
-	ret
-    })
+    }
+}
+
+/// Fill `buf` by calling `init_with`, call `callback` with it, and then drop its elements.
+///
+/// If `init_with` or `callback` panics, the elements that were initialised are still dropped.
+#[inline(always)] pub(crate) fn init_with_in<T, U, F, I>(buf: &mut [MaybeUninit<T>], mut init_with: I, callback: F) -> U
+where F: FnOnce(&mut [T]) -> U,
+      I: FnMut() -> T
+{
+    let size = buf.len();
+    let mut guard = InitGuard { ptr: buf.as_mut_ptr() as *mut T, len: 0 };
+    while guard.len < size {
+	// SAFETY: `guard.len` is within `buf`, and only `guard` is used to access `buf` from here on.
+	unsafe {
+	    guard.ptr.add(guard.len).write(init_with());
+	}
+	guard.len += 1;
+    }
+    // Drop anything `init_with` owns (e.g. the value `stackalloc()` clones) before the callback runs.
+    drop(init_with);
+    // SAFETY: We have initialised the buffer above
+    callback(unsafe { slice::from_raw_parts_mut(guard.ptr, guard.len) })
 }
 
 /// Allocate a runtime length slice of `T` on the stack, fill it by cloning `init`, call `callback` with this buffer, and then drop and deallocate the buffer.
@@ -391,27 +572,54 @@ where F: FnOnce(&mut [T]) -> U,
 where F: FnOnce(&mut [T]) -> U,
       I: IntoIterator<Item = T>,
 {
-    stackalloc_uninit(size, move |buf| {
-	let mut done = 0;
-	for (d, s) in buf.iter_mut().zip(iter.into_iter())
-	{
-	    *d = MaybeUninit::new(s);
-	    done+=1;
-	}
-	// SAFETY: We just initialised `done` elements of `buf` above.
-	let buf = unsafe {
-	    slice_assume_init_mut(&mut buf[..done])
-	};
-	let ret = callback(buf);	
-	if mem::needs_drop::<T>()
-	{
-	    // SAFETY: We have initialised the `buf` above
-	    unsafe {
-		ptr::drop_in_place(buf as *mut _);
-	    }
+    stackalloc_uninit(size, move |buf| init_from_iter_in(buf, iter, callback))
+}
+
+/// Collect up to `size` elements of an iterator into a stack allocated buffer, call `callback` with this buffer, drop and deallocate the buffer, and then return the result of `callback` along with the rest of the iterator.
+///
+/// No more than `size` elements are taken from the iterator, so none are lost, and a long iterator can be processed in batches of at most `size` elements:
+/// ```
+/// # use stackalloc::stackalloc_with_iter_rest;
+/// let mut iter = (0..100u32).peekable();
+/// let (mut batches, mut total) = (0, 0);
+/// while iter.peek().is_some() {
+///   let (sum, rest) = stackalloc_with_iter_rest(16, iter, |batch| batch.iter().sum::<u32>());
+///   iter = rest;
+///   batches += 1;
+///   total += sum;
+/// }
+/// assert_eq!((batches, total), (7, 4950));
+/// ```
+///
+/// See `stackalloc_with_iter()`.
+#[inline] pub fn stackalloc_with_iter_rest<I, T, U, F>(size: usize, iter: I, callback: F) -> (U, I::IntoIter)
+where F: FnOnce(&mut [T]) -> U,
+      I: IntoIterator<Item = T>,
+{
+    let mut iter = iter.into_iter();
+    let ret = stackalloc_with_iter(size, iter.by_ref(), callback);
+    (ret, iter)
+}
+
+/// Fill `buf` with up to `buf.len()` elements from `iter`, call `callback` with the initialised part of it, and then drop its elements.
+///
+/// If `iter` or `callback` panics, the elements that were initialised are still dropped.
+#[inline(always)] pub(crate) fn init_from_iter_in<I, T, U, F>(buf: &mut [MaybeUninit<T>], iter: I, callback: F) -> U
+where F: FnOnce(&mut [T]) -> U,
+      I: IntoIterator<Item = T>,
+{
+    let size = buf.len();
+    let mut guard = InitGuard { ptr: buf.as_mut_ptr() as *mut T, len: 0 };
+    for item in iter.into_iter().take(size)
+    {
+	// SAFETY: `guard.len` is within `buf`, and only `guard` is used to access `buf` from here on.
+	unsafe {
+	    guard.ptr.add(guard.len).write(item);
 	}
-	ret
-    })
+	guard.len += 1;
+    }
+    // SAFETY: We just initialised `guard.len` elements of `buf` above.
+    callback(unsafe { slice::from_raw_parts_mut(guard.ptr, guard.len) })
 }
 
 /// Collect an exact size iterator into a stack allocated slice, call `callback` with this buffer, and then drop and deallocate the buffer.
@@ -433,14 +641,14 @@ where F: FnOnce(&mut [T]) -> U,
 ///
 /// # Safety
 /// While the slice passed to `callback` is guaranteed to be safe to use, regardless of if the iterator fills (or tries to overfill) it,  this function is still marked as `unsafe` because it trusts the iterator `I` reports an accurate length with its `size_hint()`.
-/// It is recommended to instead use `stackalloc_with_iter()` specifying a strict upper bound on the buffer's size, or `stackalloc_from_iter_exact()` for `ExactSizeIterator`s, as this function may allocate far more, or far less (even 0) memory needed to hold all the iterator's elements; therefore this function will very easily not work properly and/or cause stack overflow if used carelessly.
+/// It is recommended to instead use `stackalloc_with_iter()` specifying a strict upper bound on the buffer's size, `stackalloc_from_iter_exact()` for `ExactSizeIterator`s, or `stackalloc_collect()` for iterators of unknown length, as this function may allocate far more, or far less (even 0) memory needed to hold all the iterator's elements; therefore this function will very easily not work properly and/or cause stack overflow if used carelessly.
 ///
 /// If the standard library's `std::iter::TrustedLen` trait becomes stablised, this function will be changed to require that as a bound on `I` and this function will no longer be `unsafe`.
 ///
 /// # Size
 /// The size allocated for the buffer will be the upper bound of the iterator's `size_hint()` if one exists. If not, then the size allocated will be the lower bound of `size_hint()`.
 /// This can potentially result in only some of the iterator being present in the buffer, or the buffer allocated being much larger than the iterator itself. 
-/// If this iterator does not have a good `size_hint()` for this purpose, use `stackalloc_with_iter()`, or `stackalloc_from_iter_exact()` if the iterator has an exact size.
+/// If this iterator does not have a good `size_hint()` for this purpose, use `stackalloc_collect()`, or `stackalloc_from_iter_exact()` if the iterator has an exact size.
 #[inline] pub unsafe fn stackalloc_from_iter_trusted<I, T, U, F>(iter: I, callback: F) -> U
 where F: FnOnce(&mut [T]) -> U,
       I: IntoIterator<Item = T>,
@@ -453,5 +661,152 @@ where F: FnOnce(&mut [T]) -> U,
 }
 
 
+
+/// Allocate a runtime length uninitialised byte buffer on the stack if there is enough stack space for it, call `callback` with this buffer, and then deallocate the buffer.
+///
+/// If allocating `size` bytes would leave less than `stack::safety_margin()` bytes of stack space for the current thread, `callback` is not called and an error is returned instead.
+/// If the remaining stack space cannot be determined on this platform, no check is performed. See the `stack` module.
+///
+/// See `alloca()`.
+#[inline] pub fn try_alloca<T, F>(size: usize, callback: F) -> Result<T, StackExhausted>
+where F: FnOnce(&mut [MaybeUninit<u8>]) -> T
+{
+    stack::check(size)?;
+    Ok(alloca(size, callback))
+}
+
+/// Allocate a runtime length zeroed byte buffer on the stack if there is enough stack space for it, call `callback` with this buffer, and then deallocate the buffer.
+///
+/// See `try_alloca()` and `alloca_zeroed()`.
+#[inline] pub fn try_alloca_zeroed<T, F>(size: usize, callback: F) -> Result<T, StackExhausted>
+where F: FnOnce(&mut [u8]) -> T
+{
+    stack::check(size)?;
+    Ok(alloca_zeroed(size, callback))
+}
+
+/// Allocate a runtime length uninitialised byte buffer aligned to `align` on the stack if there is enough stack space for it, call `callback` with this buffer, and then deallocate the buffer.
+///
+/// If `align` is not a power of 2 or the size of the buffer overflows (see `Layout::from_size_align()`), or there is not enough stack space for it (see `try_alloca()`), `callback` is not called and an error is returned instead.
+///
+/// See `alloca_aligned()`.
+#[inline] pub fn try_alloca_aligned<T, F>(size: usize, align: usize, callback: F) -> Result<T, TryAllocError>
+where F: FnOnce(&mut [MaybeUninit<u8>]) -> T
+{
+    let layout = Layout::from_size_align(size, align)?;
+    stack::check(padded_size(layout))?;
+    Ok(alloca_aligned(size, align, callback))
+}
+
+/// Allocate a runtime length slice of uninitialised `T` on the stack if there is enough stack space for it, call `callback` with this buffer, and then deallocate the buffer.
+///
+/// If the size of the buffer overflows (see `Layout::array()`), or there is not enough stack space for it (see `try_alloca()`), `callback` is not called and an error is returned instead.
+/// The other typed `try_` functions behave the same way.
+///
+/// See `stackalloc_uninit()`.
+#[inline] pub fn try_stackalloc_uninit<T, U, F>(size: usize, callback: F) -> Result<U, TryAllocError>
+where F: FnOnce(&mut [MaybeUninit<T>]) -> U
+{
+    stack::check(padded_size(Layout::array::<T>(size)?))?;
+    Ok(stackalloc_uninit(size, callback))
+}
+
+/// Allocate a runtime length slice of `T` on the stack if there is enough stack space for it, fill it by calling `init_with`, call `callback` with this buffer, and then drop and deallocate the buffer.
+///
+/// See `try_alloca()` and `stackalloc_with()`.
+#[inline] pub fn try_stackalloc_with<T, U, F, I>(size: usize, init_with: I, callback: F) -> Result<U, TryAllocError>
+where F: FnOnce(&mut [T]) -> U,
+      I: FnMut() -> T
+{
+    stack::check(padded_size(Layout::array::<T>(size)?))?;
+    Ok(stackalloc_with(size, init_with, callback))
+}
+
+/// Allocate a runtime length slice of `T` on the stack if there is enough stack space for it, fill it by cloning `init`, call `callback` with this buffer, and then drop and deallocate the buffer.
+///
+/// See `try_alloca()` and `stackalloc()`.
+#[inline] pub fn try_stackalloc<T, U, F>(size: usize, init: T, callback: F) -> Result<U, TryAllocError>
+where F: FnOnce(&mut [T]) -> U,
+      T: Clone
+{
+    try_stackalloc_with(size, move || init.clone(), callback)
+}
+
+/// Allocate a runtime length slice of `T` on the stack if there is enough stack space for it, fill it by calling `T::default()`, call `callback` with this buffer, and then drop and deallocate the buffer.
+///
+/// See `try_alloca()` and `stackalloc_with_default()`.
+#[inline] pub fn try_stackalloc_with_default<T, U, F>(size: usize, callback: F) -> Result<U, TryAllocError>
+where F: FnOnce(&mut [T]) -> U,
+      T: Default
+{
+    try_stackalloc_with(size, T::default, callback)
+}
+
+/// Collect an iterator into a stack allocated buffer of up to `size` elements if there is enough stack space for it, call `callback` with this buffer, and then drop and deallocate the buffer.
+///
+/// See `try_alloca()` and `stackalloc_with_iter()`.
+#[inline] pub fn try_stackalloc_with_iter<I, T, U, F>(size: usize, iter: I, callback: F) -> Result<U, TryAllocError>
+where F: FnOnce(&mut [T]) -> U,
+      I: IntoIterator<Item = T>,
+{
+    stack::check(padded_size(Layout::array::<T>(size)?))?;
+    Ok(stackalloc_with_iter(size, iter, callback))
+}
+
+/// Allocate a runtime length buffer of `capacity` uninitialised `T` on the stack, call `callback` with an `AVec` backed by this buffer, and then drop and deallocate the buffer.
+///
+/// The `AVec` moves its elements to the heap if more than `capacity` elements are pushed into it.
+/// Its elements are dropped when `callback` returns or panics, regardless of whether they are on the stack or the heap.
+///
+/// See `stackalloc_uninit()`.
+///
+/// # Example
+/// ```
+/// # use stackalloc::stackalloc_avec;
+/// let sum = stackalloc_avec(8, |vec| {
+///  vec.extend(1..=10u64); // The last 2 elements move the vector to the heap
+///  vec.iter().sum::<u64>()
+/// });
+/// assert_eq!(sum, 55);
+/// ```
+#[cfg(not(feature = "no_std"))]
+#[inline] pub fn stackalloc_avec<T, U, F>(capacity: usize, callback: F) -> U
+where F: FnOnce(&mut AVec<'_, T>) -> U
+{
+    stackalloc_uninit(capacity, move |buf| {
+	let mut vec = AVec::new(buf);
+	callback(&mut vec)
+    })
+}
+
+
+/// Allocate a runtime length byte buffer of `capacity` bytes on the stack, call `callback` with an empty `StackStr` backed by this buffer, and then deallocate the buffer.
+///
+/// The string cannot grow beyond `capacity` bytes unless spilling to the heap is enabled with `StackStr::set_spill()`.
+///
+/// See `stackalloc_uninit()`.
+///
+/// # Example
+/// ```
+/// # use stackalloc::stackalloc_str;
+/// use std::fmt::Write;
+/// let len = stackalloc_str(32, |s| {
+///  write!(s, "user.{}.{}", 1001, "name").unwrap();
+///  assert_eq!(&s[..], "user.1001.name");
+///  s.len()
+/// });
+/// assert_eq!(len, 14);
+/// ```
+#[cfg(not(feature = "no_std"))]
+#[inline] pub fn stackalloc_str<U, F>(capacity: usize, callback: F) -> U
+where F: FnOnce(&mut StackStr<'_>) -> U
+{
+    stackalloc_uninit(capacity, move |buf| {
+	let mut string = StackStr::new(buf);
+	callback(&mut string)
+    })
+}
+
+
 #[cfg(test)]
 mod tests;
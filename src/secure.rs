@@ -0,0 +1,119 @@
+//! Stack allocations that are wiped when they are deallocated, for holding secrets such as keys and passwords.
+//!
+//! Normally the contents of a stack allocated buffer are left behind on the stack after it is deallocated, until they are overwritten by a later function call.
+//! The functions in this module overwrite the whole allocation (including any alignment padding) with zeroes after the callback returns or panics, in a way that the compiler cannot optimise out.
+//!
+//! With the `zeroize` feature, the wipe is done with the `zeroize` crate instead.
+//!
+//! # Example
+//! ```
+//! # use stackalloc::alloca_secure;
+//! let password = "hunter2";
+//! let len = alloca_secure(password.len(), |buf| {
+//!   buf.copy_from_slice(password.as_bytes());
+//!   // ...
+//!   buf.len()
+//! }); // `buf` has been zeroed here
+//! assert_eq!(len, 7);
+//! ```
+use super::*;
+
+/// Overwrite `buf` with zeroes, in a way that will not be optimised out.
+#[cfg(not(feature = "zeroize"))]
+#[inline(never)] pub(crate) fn wipe(buf: &mut [MaybeUninit<u8>])
+{
+    for byte in buf.iter_mut() {
+	// SAFETY: `byte` is a valid reference.
+	unsafe {
+	    ptr::write_volatile(byte, MaybeUninit::new(0));
+	}
+    }
+    core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+}
+
+/// Overwrite `buf` with zeroes, in a way that will not be optimised out.
+#[cfg(feature = "zeroize")]
+#[inline(never)] pub(crate) fn wipe(buf: &mut [MaybeUninit<u8>])
+{
+    zeroize::Zeroize::zeroize(buf);
+}
+
+/// Wipes the buffer at `ptr` when dropped.
+struct Wipe(*mut MaybeUninit<u8>, usize);
+
+impl Drop for Wipe
+{
+    #[inline] fn drop(&mut self)
+    {
+	// SAFETY: The buffer is valid until the end of the callback of `alloca()` this was created in, and its other borrows have ended by the time this is dropped.
+	wipe(unsafe { slice::from_raw_parts_mut(self.0, self.1) })
+    }
+}
+
+/// Call `callback` with `buf`, and then wipe `buf`, even if `callback` panics.
+#[inline(always)] pub(crate) fn wiping<T, F>(buf: &mut [MaybeUninit<u8>], callback: F) -> T
+where F: FnOnce(&mut [MaybeUninit<u8>]) -> T
+{
+    let wipe = Wipe(buf.as_mut_ptr(), buf.len());
+    // SAFETY: Only `wipe` is used to access `buf` from here on, and the slice is not used after `callback` returns.
+    callback(unsafe { slice::from_raw_parts_mut(wipe.0, wipe.1) })
+}
+
+/// Allocate `size` bytes aligned to `align` on the stack, call `callback` with this buffer, wipe the whole allocation, and then deallocate it.
+#[inline(always)] fn alloca_secure_aligned<T, F>(size: usize, align: usize, callback: F) -> T
+where F: FnOnce(&mut [MaybeUninit<u8>]) -> T
+{
+    let padded = size.checked_add(align - 1).expect("alloca_secure: allocation size overflow");
+    alloca(padded, move |buf| wiping(buf, move |buf| {
+	let padding = align_padding(buf.as_ptr() as *const u8, align);
+	// SAFETY: `buf` is `size + align - 1` bytes long, and `padding` is less than `align`.
+	callback(unsafe { slice::from_raw_parts_mut(buf.as_mut_ptr().add(padding), size) })
+    }))
+}
+
+/// Allocate a runtime length zeroed byte buffer on the stack, call `callback` with this buffer, and then wipe and deallocate the buffer.
+///
+/// The buffer is wiped even if `callback` panics.
+///
+/// See `alloca_zeroed()`.
+#[inline] pub fn alloca_secure<T, F>(size: usize, callback: F) -> T
+where F: FnOnce(&mut [u8]) -> T
+{
+    alloca_secure_aligned(size, 1, move |buf| {
+	// SAFETY: We zero-initialise the backing slice
+	callback(unsafe {
+	    ptr::write_bytes(buf.as_mut_ptr(), 0, buf.len());
+	    slice_assume_init_mut(buf)
+	})
+    })
+}
+
+/// Allocate a runtime length slice of `T` on the stack, fill it by calling `init_with`, call `callback` with this buffer, and then drop its elements and wipe and deallocate the buffer.
+///
+/// The elements are dropped before the buffer is wiped, and the buffer is wiped even if `init_with` or `callback` panics.
+///
+/// See `stackalloc_with()`.
+///
+/// # Panics
+/// If the size of the buffer overflows (see `Layout::array()`.)
+#[inline] pub fn stackalloc_secure_with<T, U, F, I>(size: usize, init_with: I, callback: F) -> U
+where F: FnOnce(&mut [T]) -> U,
+      I: FnMut() -> T
+{
+    let layout = array_layout::<T>(size);
+    alloca_secure_aligned(layout.size(), layout.align(), move |buf| {
+	// SAFETY: `buf` is aligned to `T` and is large enough to hold `size` elements of it.
+	let buf = unsafe { slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut MaybeUninit<T>, size) };
+	init_with_in(buf, init_with, callback)
+    })
+}
+
+/// Allocate a runtime length slice of `T` on the stack, fill it by cloning `init`, call `callback` with this buffer, and then drop its elements and wipe and deallocate the buffer.
+///
+/// See `stackalloc_secure_with()`.
+#[inline] pub fn stackalloc_secure<T, U, F>(size: usize, init: T, callback: F) -> U
+where F: FnOnce(&mut [T]) -> U,
+      T: Clone
+{
+    stackalloc_secure_with(size, move || init.clone(), callback)
+}
@@ -0,0 +1,184 @@
+//! Querying the remaining stack space of the current thread.
+//!
+//! The `try_` family of functions (e.g. `try_alloca()`) use this to refuse allocations that would leave less than `safety_margin()` bytes of stack space, instead of overflowing the stack.
+//!
+//! # Platform support
+//! The remaining stack space can currently only be determined on Linux (and not with the `no_std` feature.) On other platforms `remaining()` returns `None`, and no check is performed by the `try_` functions.
+use core::{
+    fmt,
+    alloc::LayoutError,
+    sync::atomic::{
+	AtomicUsize,
+	Ordering,
+    },
+};
+
+/// The default value of `safety_margin()`.
+pub const DEFAULT_SAFETY_MARGIN: usize = 64 * 1024;
+
+static SAFETY_MARGIN: AtomicUsize = AtomicUsize::new(DEFAULT_SAFETY_MARGIN);
+
+/// The number of bytes of stack space that the `try_` functions will always leave free.
+///
+/// This should be large enough to account for the stack usage of the callback (and anything it calls) as well.
+#[inline] pub fn safety_margin() -> usize
+{
+    SAFETY_MARGIN.load(Ordering::Relaxed)
+}
+
+/// Set the number of bytes of stack space that the `try_` functions will always leave free.
+///
+/// This is global to all threads. The default is `DEFAULT_SAFETY_MARGIN`.
+#[inline] pub fn set_safety_margin(bytes: usize)
+{
+    SAFETY_MARGIN.store(bytes, Ordering::Relaxed)
+}
+
+/// The number of bytes between the current stack pointer and the end of the current thread's stack.
+///
+/// Returns `None` if this cannot be determined on this platform.
+#[inline(never)] pub fn remaining() -> Option<usize>
+{
+    let marker = 0u8;
+    let sp = &marker as *const u8 as usize;
+    stack_low().map(|low| sp.saturating_sub(low))
+}
+
+/// The number of bytes that can be allocated on the stack by a `try_` function right now, leaving `safety_margin()` bytes free.
+///
+/// Returns `None` if this cannot be determined on this platform.
+#[inline] pub fn available() -> Option<usize>
+{
+    remaining().map(|rem| rem.saturating_sub(safety_margin()))
+}
+
+/// Check if `requested` bytes can be allocated on the stack, and are within the current thread's budget (see `budget`.)
+#[inline] pub(crate) fn check(requested: usize) -> Result<(), StackExhausted>
+{
+    #[cfg(not(feature = "no_std"))]
+    let available = match (available(), crate::budget::available()) {
+	(Some(stack), Some(budget)) => Some(stack.min(budget)),
+	(stack, budget) => stack.or(budget),
+    };
+    #[cfg(feature = "no_std")]
+    let available = available();
+
+    match available {
+	Some(available) if requested > available => Err(StackExhausted { requested, available }),
+	_ => Ok(()),
+    }
+}
+
+/// The lowest address of the current thread's stack.
+#[cfg(all(target_os = "linux", not(feature = "no_std")))]
+fn stack_low() -> Option<usize>
+{
+    use std::cell::Cell;
+    thread_local! {
+	static STACK_LOW: Cell<Option<usize>> = const { Cell::new(None) };
+    }
+
+    /// Ask pthreads for the bounds of this thread's stack.
+    ///
+    /// For the main thread, this reads `/proc/self/maps` so we only want to do it once per thread.
+    unsafe fn query() -> Option<usize>
+    {
+	let mut attr = core::mem::MaybeUninit::<libc::pthread_attr_t>::uninit();
+	if libc::pthread_getattr_np(libc::pthread_self(), attr.as_mut_ptr()) != 0 {
+	    return None;
+	}
+	let mut addr = core::ptr::null_mut();
+	let mut size = 0;
+	let rc = libc::pthread_attr_getstack(attr.as_ptr(), &mut addr, &mut size);
+	libc::pthread_attr_destroy(attr.as_mut_ptr());
+	if rc == 0 {
+	    Some(addr as usize)
+	} else {
+	    None
+	}
+    }
+
+    STACK_LOW.try_with(|low| {
+	if low.get().is_none() {
+	    low.set(unsafe { query() });
+	}
+	low.get()
+    }).ok().flatten()
+}
+
+#[cfg(not(all(target_os = "linux", not(feature = "no_std"))))]
+#[inline(always)] fn stack_low() -> Option<usize>
+{
+    None
+}
+
+/// Returned by the `try_` functions when an allocation would leave less than `safety_margin()` bytes of stack space, or exceed the current thread's budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StackExhausted
+{
+    /// The number of bytes that were requested.
+    pub requested: usize,
+    /// The number of bytes that could have been allocated.
+    pub available: usize,
+}
+
+impl fmt::Display for StackExhausted
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+	write!(f, "not enough stack space to allocate {} bytes ({} available)", self.requested, self.available)
+    }
+}
+
+#[cfg(not(feature = "no_std"))]
+impl std::error::Error for StackExhausted{}
+
+/// Returned by the typed `try_` functions (e.g. `try_stackalloc_uninit()`) when the buffer cannot be allocated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TryAllocError
+{
+    /// The size of the buffer overflows (see `Layout::array()`.)
+    Layout(LayoutError),
+    /// There is not enough stack space for the buffer.
+    StackExhausted(StackExhausted),
+}
+
+impl From<LayoutError> for TryAllocError
+{
+    #[inline] fn from(from: LayoutError) -> Self
+    {
+	Self::Layout(from)
+    }
+}
+
+impl From<StackExhausted> for TryAllocError
+{
+    #[inline] fn from(from: StackExhausted) -> Self
+    {
+	Self::StackExhausted(from)
+    }
+}
+
+/// The details are in `Error::source()`.
+impl fmt::Display for TryAllocError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+	match self {
+	    Self::Layout(_) => write!(f, "invalid stack allocation size"),
+	    Self::StackExhausted(_) => write!(f, "cannot allocate on the stack"),
+	}
+    }
+}
+
+#[cfg(not(feature = "no_std"))]
+impl std::error::Error for TryAllocError
+{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)>
+    {
+	Some(match self {
+	    Self::Layout(layout) => layout,
+	    Self::StackExhausted(exhausted) => exhausted,
+	})
+    }
+}
@@ -0,0 +1,210 @@
+//! An owning pointer to a (possibly unsized) value in stack allocated memory, like `Box`.
+//!
+//! # Unsized values
+//! A `StackBox<T>` can be converted to a `StackBox<dyn Trait>` (or `StackBox<[T]>` from an array) with the `stackbox!` macro.
+//! With the `coerce_unsized` feature (nightly only), this conversion is also done by implicit coercion, like with `Box`.
+//! ```
+//! # use stackalloc::{stackalloc_box, stackbox, StackBox};
+//! # use std::fmt::Display;
+//! let string = stackalloc_box(100u32, |boxed| {
+//!   let boxed: StackBox<dyn Display> = stackbox!(boxed => dyn Display);
+//!   boxed.to_string()
+//! });
+//! assert_eq!(string, "100");
+//! ```
+use super::*;
+use core::{
+    fmt,
+    marker::PhantomData,
+    ops::{
+	Deref,
+	DerefMut,
+    },
+};
+
+/// An owning pointer to a value of type `T` in memory that lives for `'a`.
+///
+/// The value is dropped when the `StackBox` is, but the memory it is in is not deallocated until the function that created it (e.g. `stackalloc_box()`) returns.
+pub struct StackBox<'a, T: ?Sized>
+{
+    ptr: ptr::NonNull<T>,
+    _marker: PhantomData<(&'a mut (), T)>,
+}
+
+unsafe impl<'a, T: ?Sized + Send> Send for StackBox<'a, T>{}
+unsafe impl<'a, T: ?Sized + Sync> Sync for StackBox<'a, T>{}
+
+impl<'a, T: ?Sized> StackBox<'a, T>
+{
+    /// Create a `StackBox` that owns the value at `ptr`.
+    ///
+    /// # Safety
+    /// `ptr` must be non-null, aligned, and point to a valid `T` that is not owned or referenced by anything else, in memory that lives for `'a`.
+    #[inline] pub unsafe fn from_raw(ptr: *mut T) -> Self
+    {
+	Self {
+	    ptr: ptr::NonNull::new_unchecked(ptr),
+	    _marker: PhantomData,
+	}
+    }
+
+    /// Consume the `StackBox`, returning a pointer to the value without dropping it.
+    #[inline] pub fn into_raw(this: Self) -> *mut T
+    {
+	ManuallyDrop::new(this).ptr.as_ptr()
+    }
+
+    /// Consume the `StackBox`, returning a reference to the value that will never be dropped.
+    #[inline] pub fn leak(this: Self) -> &'a mut T
+    {
+	// SAFETY: We own the value, and its memory lives for `'a`.
+	unsafe { &mut *Self::into_raw(this) }
+    }
+
+    /// Convert the pointer to the value with `map`. This is used by the `stackbox!` macro to perform unsized coercions.
+    ///
+    /// # Safety
+    /// `map` must return a pointer to the same value as the one it is given, and the result must satisfy the requirements of `StackBox::from_raw()`.
+    #[inline] pub unsafe fn map_raw<U: ?Sized, F>(this: Self, map: F) -> StackBox<'a, U>
+    where F: FnOnce(*mut T) -> *mut U
+    {
+	StackBox::from_raw(map(Self::into_raw(this)))
+    }
+}
+
+impl<'a, T> StackBox<'a, T>
+{
+    /// Move the value out of the `StackBox`.
+    #[inline] pub fn into_inner(this: Self) -> T
+    {
+	// SAFETY: We own the value, and `into_raw()` prevents it being dropped again.
+	unsafe { ptr::read(Self::into_raw(this)) }
+    }
+}
+
+impl<'a, T: ?Sized> Drop for StackBox<'a, T>
+{
+    fn drop(&mut self)
+    {
+	// SAFETY: We own the value, and it is only dropped here.
+	unsafe {
+	    ptr::drop_in_place(self.ptr.as_ptr());
+	}
+    }
+}
+
+impl<'a, T: ?Sized> Deref for StackBox<'a, T>
+{
+    type Target = T;
+    #[inline] fn deref(&self) -> &Self::Target {
+	// SAFETY: We own the value.
+	unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for StackBox<'a, T>
+{
+    #[inline] fn deref_mut(&mut self) -> &mut Self::Target {
+	// SAFETY: We own the value.
+	unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<'a, T: ?Sized> AsRef<T> for StackBox<'a, T>
+{
+    #[inline] fn as_ref(&self) -> &T {
+	self
+    }
+}
+
+impl<'a, T: ?Sized> AsMut<T> for StackBox<'a, T>
+{
+    #[inline] fn as_mut(&mut self) -> &mut T {
+	self
+    }
+}
+
+impl<'a, T: ?Sized + fmt::Debug> fmt::Debug for StackBox<'a, T>
+{
+    #[inline] fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<'a, T: ?Sized + fmt::Display> fmt::Display for StackBox<'a, T>
+{
+    #[inline] fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	fmt::Display::fmt(&**self, f)
+    }
+}
+
+#[cfg(feature = "coerce_unsized")]
+impl<'a, T: ?Sized + core::marker::Unsize<U>, U: ?Sized> core::ops::CoerceUnsized<StackBox<'a, U>> for StackBox<'a, T>{}
+
+/// Convert a `StackBox<T>` to a `StackBox<U>` where `T` can be unsized to `U` (e.g. `T` to `dyn Trait`, or `[T; N]` to `[T]`.)
+///
+/// This is `stackbox!(boxed => U)`.
+/// If the trait object is not `'static`, a lifetime bound must be given (e.g. `stackbox!(boxed => dyn Trait + '_)`.)
+#[macro_export]
+macro_rules! stackbox {
+    ($boxed:expr => $to:ty) => {
+	match $boxed {
+	    // SAFETY: The closure can only perform an unsized coercion of the pointer.
+	    boxed => unsafe { $crate::StackBox::map_raw(boxed, |ptr| -> *mut $to { ptr }) },
+	}
+    };
+}
+
+/// Move `value` into stack allocated memory, call `callback` with an owning pointer to it, and then drop and deallocate it.
+///
+/// The value is dropped when the `StackBox` is, unless it is moved out with `StackBox::into_inner()` (or leaked.)
+///
+/// See `stackalloc_uninit()`.
+#[inline] pub fn stackalloc_box<T, U, F>(value: T, callback: F) -> U
+where F: FnOnce(StackBox<'_, T>) -> U
+{
+    stackalloc_uninit(1, move |buf| {
+	let slot: *mut T = buf[0].as_mut_ptr();
+	// SAFETY: `slot` is aligned and valid for a `T`, and we initialise it before creating the `StackBox`.
+	unsafe {
+	    slot.write(value);
+	    callback(StackBox::from_raw(slot))
+	}
+    })
+}
+
+/// Allocate a buffer of `layout` on the stack, initialise a (possibly unsized) value in it with `init`, call `callback` with an owning pointer to it, and then drop and deallocate it.
+///
+/// `init` is given a pointer to the start of the buffer, which is aligned to `layout.align()` and valid for `layout.size()` bytes.
+///
+/// See `alloca_aligned()`.
+///
+/// # Safety
+/// `init` must initialise a valid `T` in the buffer, and return a pointer to it that satisfies the requirements of `StackBox::from_raw()`.
+///
+/// # Example
+/// ```
+/// # use stackalloc::stackalloc_dyn;
+/// # use std::{alloc::Layout, ptr};
+/// let string = "hello world";
+/// let len = unsafe {
+///   stackalloc_dyn(Layout::for_value(string), |buf| {
+///     ptr::copy_nonoverlapping(string.as_ptr(), buf, string.len());
+///     ptr::slice_from_raw_parts_mut(buf, string.len()) as *mut str
+///   }, |boxed| {
+///     assert_eq!(&*boxed, "hello world");
+///     boxed.len()
+///   })
+/// };
+/// assert_eq!(len, 11);
+/// ```
+#[inline] pub unsafe fn stackalloc_dyn<T: ?Sized, U, I, F>(layout: Layout, init: I, callback: F) -> U
+where I: FnOnce(*mut u8) -> *mut T,
+      F: FnOnce(StackBox<'_, T>) -> U
+{
+    if layout.size() == 0 {
+	// Nothing needs to be allocated, the pointer only needs to be non-null and aligned.
+	return callback(StackBox::from_raw(init(layout.align() as *mut u8)));
+    }
+    alloca_aligned(layout.size(), layout.align(), move |buf| callback(StackBox::from_raw(init(buf.as_mut_ptr() as *mut u8))))
+}
@@ -0,0 +1,113 @@
+//! Per-thread statistics of the allocations made through `alloca()`, for profiling stack usage.
+//!
+//! Only available with the `stats` feature. Every allocation made by this crate on the stack is recorded, including those that fit in the inline array of the `_bounded` functions (but not zero-sized allocations.)
+//!
+//! # Example
+//! ```
+//! # use stackalloc::{alloca_zeroed, stats};
+//! stats::reset();
+//! alloca_zeroed(100, |_| alloca_zeroed(1000, |_| ()));
+//! let stats = stats::snapshot();
+//! assert_eq!(stats.count, 2);
+//! assert_eq!(stats.total_bytes, 1100);
+//! assert_eq!(stats.peak_bytes, 1100);
+//! assert_eq!(stats.histogram[stats::bucket(100)], 1);
+//! ```
+use std::cell::RefCell;
+
+/// The number of buckets in `Stats::histogram`.
+pub const BUCKETS: usize = usize::BITS as usize + 1;
+
+/// The index of the bucket in `Stats::histogram` an allocation of `size` bytes is counted in.
+///
+/// Bucket 0 counts allocations of 0 bytes, and bucket `n` counts allocations of `2^(n-1)` up to `2^n - 1` bytes.
+#[inline] pub fn bucket(size: usize) -> usize
+{
+    (usize::BITS - size.leading_zeros()) as usize
+}
+
+/// Statistics of the allocations made on one thread.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Stats
+{
+    /// The number of allocations made.
+    pub count: u64,
+    /// The sum of the sizes of all allocations made.
+    pub total_bytes: u64,
+    /// The number of bytes currently allocated.
+    pub current_bytes: usize,
+    /// The highest number of bytes that were allocated at the same time.
+    pub peak_bytes: usize,
+    /// The number of allocations made of each size (see `bucket()`.)
+    pub histogram: [u64; BUCKETS],
+}
+
+impl Default for Stats
+{
+    #[inline] fn default() -> Self
+    {
+	Self {
+	    count: 0,
+	    total_bytes: 0,
+	    current_bytes: 0,
+	    peak_bytes: 0,
+	    histogram: [0; BUCKETS],
+	}
+    }
+}
+
+thread_local! {
+    static STATS: RefCell<Stats> = RefCell::new(Stats::default());
+}
+
+/// The statistics of the current thread.
+#[inline] pub fn snapshot() -> Stats
+{
+    STATS.with(|stats| stats.borrow().clone())
+}
+
+/// Reset the statistics of the current thread.
+///
+/// Allocations that are still live are kept in `current_bytes` (and so `peak_bytes`.)
+#[inline] pub fn reset()
+{
+    STATS.with(|stats| {
+	let mut stats = stats.borrow_mut();
+	let current = stats.current_bytes;
+	*stats = Stats {
+	    current_bytes: current,
+	    peak_bytes: current,
+	    ..Stats::default()
+	};
+    })
+}
+
+/// Records an allocation of `size` bytes until it is dropped.
+pub(crate) struct Record(usize);
+
+impl Record
+{
+    #[inline] pub(crate) fn new(size: usize) -> Self
+    {
+	let _ = STATS.try_with(|stats| {
+	    let mut stats = stats.borrow_mut();
+	    stats.count += 1;
+	    stats.total_bytes = stats.total_bytes.saturating_add(size as u64);
+	    stats.current_bytes = stats.current_bytes.saturating_add(size);
+	    stats.peak_bytes = stats.peak_bytes.max(stats.current_bytes);
+	    stats.histogram[bucket(size)] += 1;
+	});
+	Self(size)
+    }
+}
+
+impl Drop for Record
+{
+    #[inline] fn drop(&mut self)
+    {
+	let _ = STATS.try_with(|stats| {
+	    let mut stats = stats.borrow_mut();
+	    stats.current_bytes = stats.current_bytes.saturating_sub(self.0);
+	});
+    }
+}
@@ -0,0 +1,227 @@
+//! A `String`-like wrapper type over a stack allocated byte buffer.
+use std::mem::MaybeUninit;
+use std::ops::{
+    Deref,
+    DerefMut,
+};
+use std::{
+    fmt,
+    str,
+};
+use super::AVec;
+
+/// Returned when a `StackStr` that does not spill to the heap has no space left for the string being pushed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct CapacityError;
+
+impl fmt::Display for CapacityError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+	write!(f, "insufficient capacity in stack string buffer")
+    }
+}
+
+impl std::error::Error for CapacityError{}
+
+/// A growable UTF-8 string with a backing byte slice.
+///
+/// By default pushing more than the backing slice can hold fails. If spilling is enabled with `set_spill(true)`, the string is instead moved to the heap, in the same way as `AVec`.
+pub struct StackStr<'a>
+{
+    buf: AVec<'a, u8>,
+    spill: bool,
+}
+
+impl<'a> StackStr<'a>
+{
+    /// Create a new empty `StackStr` with this backing buffer.
+    #[inline] pub fn new(stack: &'a mut [MaybeUninit<u8>]) -> Self
+    {
+	Self {
+	    buf: AVec::new(stack),
+	    spill: false,
+	}
+    }
+
+    /// Set whether this string moves to the heap when the backing buffer is exhausted, instead of failing.
+    #[inline] pub fn set_spill(&mut self, spill: bool)
+    {
+	self.spill = spill;
+    }
+
+    /// Will this string move to the heap when the backing buffer is exhausted?
+    #[inline] pub fn spills(&self) -> bool
+    {
+	self.spill
+    }
+
+    /// Has the string been moved to the heap?
+    #[inline] pub fn is_allocated(&self) -> bool
+    {
+	self.buf.is_allocated()
+    }
+
+    /// The number of bytes this string can hold before it has to (re)allocate.
+    #[inline] pub fn capacity(&self) -> usize
+    {
+	self.buf.capacity()
+    }
+
+    /// Can `bytes` more bytes be pushed?
+    #[inline] fn fits(&self, bytes: usize) -> bool
+    {
+	self.spill || self.is_allocated() || bytes <= self.buf.capacity() - self.buf.len()
+    }
+
+    /// Append `string` to the end of this string, or return an error if there is not enough space for it.
+    ///
+    /// If this fails, the string is not modified.
+    pub fn try_push_str(&mut self, string: &str) -> Result<(), CapacityError>
+    {
+	if !self.fits(string.len()) {
+	    return Err(CapacityError);
+	}
+	self.buf.extend_from_slice(string.as_bytes());
+	Ok(())
+    }
+
+    /// Append `ch` to the end of this string, or return an error if there is not enough space for it.
+    ///
+    /// If this fails, the string is not modified.
+    #[inline] pub fn try_push(&mut self, ch: char) -> Result<(), CapacityError>
+    {
+	self.try_push_str(ch.encode_utf8(&mut [0; 4]))
+    }
+
+    /// Append `string` to the end of this string.
+    ///
+    /// # Panics
+    /// If there is not enough space for `string` and spilling is disabled.
+    #[inline] pub fn push_str(&mut self, string: &str)
+    {
+	self.try_push_str(string).expect("stack string capacity exceeded")
+    }
+
+    /// Append `ch` to the end of this string.
+    ///
+    /// # Panics
+    /// If there is not enough space for `ch` and spilling is disabled.
+    #[inline] pub fn push(&mut self, ch: char)
+    {
+	self.try_push(ch).expect("stack string capacity exceeded")
+    }
+
+    /// Remove the last character from this string and return it, or `None` if it is empty.
+    pub fn pop(&mut self) -> Option<char>
+    {
+	let ch = self.chars().next_back()?;
+	self.buf.truncate(self.len() - ch.len_utf8());
+	Some(ch)
+    }
+
+    /// Shorten this string to `len` bytes.
+    ///
+    /// If `len` is greater than the current length, this has no effect.
+    ///
+    /// # Panics
+    /// If `len` does not lie on a `char` boundary.
+    pub fn truncate(&mut self, len: usize)
+    {
+	if len < self.len() {
+	    assert!(self.is_char_boundary(len), "new length is not on a char boundary");
+	    self.buf.truncate(len);
+	}
+    }
+
+    /// Remove all the contents of this string.
+    #[inline] pub fn clear(&mut self)
+    {
+	self.buf.clear()
+    }
+
+    /// The contents of this string.
+    #[inline] pub fn as_str(&self) -> &str
+    {
+	// SAFETY: Only valid UTF-8 is ever pushed into the buffer, and it is only truncated on char boundaries.
+	unsafe {
+	    str::from_utf8_unchecked(&self.buf[..])
+	}
+    }
+
+    /// The mutable contents of this string.
+    #[inline] pub fn as_mut_str(&mut self) -> &mut str
+    {
+	// SAFETY: Only valid UTF-8 is ever pushed into the buffer, and it is only truncated on char boundaries.
+	unsafe {
+	    str::from_utf8_unchecked_mut(&mut self.buf[..])
+	}
+    }
+}
+
+impl<'a> Deref for StackStr<'a>
+{
+    type Target = str;
+    #[inline] fn deref(&self) -> &Self::Target {
+	self.as_str()
+    }
+}
+
+impl<'a> DerefMut for StackStr<'a>
+{
+    #[inline] fn deref_mut(&mut self) -> &mut Self::Target {
+	self.as_mut_str()
+    }
+}
+
+impl<'a> AsRef<str> for StackStr<'a>
+{
+    #[inline] fn as_ref(&self) -> &str {
+	self.as_str()
+    }
+}
+
+impl<'a> AsRef<[u8]> for StackStr<'a>
+{
+    #[inline] fn as_ref(&self) -> &[u8] {
+	self.as_bytes()
+    }
+}
+
+impl<'a> fmt::Write for StackStr<'a>
+{
+    #[inline] fn write_str(&mut self, s: &str) -> fmt::Result {
+	self.try_push_str(s).map_err(|_| fmt::Error)
+    }
+    #[inline] fn write_char(&mut self, c: char) -> fmt::Result {
+	self.try_push(c).map_err(|_| fmt::Error)
+    }
+}
+
+impl<'a> fmt::Display for StackStr<'a>
+{
+    #[inline] fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	fmt::Display::fmt(self.as_str(), f)
+    }
+}
+
+impl<'a> fmt::Debug for StackStr<'a>
+{
+    #[inline] fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl<'a> PartialEq<str> for StackStr<'a>
+{
+    #[inline] fn eq(&self, other: &str) -> bool {
+	self.as_str() == other
+    }
+}
+
+impl<'a, 'b> PartialEq<&'b str> for StackStr<'a>
+{
+    #[inline] fn eq(&self, other: &&'b str) -> bool {
+	self.as_str() == *other
+    }
+}
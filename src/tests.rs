@@ -32,7 +32,7 @@ fn exact_size_iter()
     }), result);
 }
 
-#[cfg(all(nightly, not(feature="no_std")))] // XXX: process will abort on no_std. This is expected, but won't "pass" this test.
+#[cfg(nightly)] // Without `std`, the panic unwinds through the trampoline instead of being caught and resumed.
 #[test]
 #[should_panic]
 fn unwinding_over_boundary()
@@ -58,6 +58,8 @@ fn with_alloca()
 
     assert_eq!(sum, (1..=SIZE).sum::<usize>() as u64); 
 }
+// The raw callback is `C-unwind` without `std` on nightly when panics unwind.
+#[cfg(not(all(unwind_trampoline, panic = "unwind")))]
 #[test]
 fn raw_trampoline()
 {
@@ -190,4 +192,948 @@ mod bench
 	    black_box(crate::alloca_zeroed(SIZE, |b| {black_box(b);}));
 	})
     }
+
+    const SMALL_SIZE: usize = 64;
+
+    #[bench]
+    fn stackalloc_of_uninit_bytes_small(b: &mut Bencher)
+    {
+	let size = black_box(SMALL_SIZE);
+	b.iter(|| crate::alloca(size, |buf| black_box(buf).as_ptr()))
+    }
+    #[bench]
+    fn bounded_of_uninit_bytes_small(b: &mut Bencher)
+    {
+	let size = black_box(SMALL_SIZE);
+	b.iter(|| crate::alloca_bounded::<SMALL_SIZE, _, _>(size, |buf| black_box(buf).as_ptr()))
+    }
+    #[bench]
+    fn stackalloc_of_zeroed_bytes_small(b: &mut Bencher)
+    {
+	let size = black_box(SMALL_SIZE);
+	b.iter(|| crate::alloca_zeroed(size, |buf| black_box(buf)[size - 1]))
+    }
+    #[bench]
+    fn bounded_of_zeroed_bytes_small(b: &mut Bencher)
+    {
+	let size = black_box(SMALL_SIZE);
+	b.iter(|| crate::alloca_zeroed_bounded::<SMALL_SIZE, _, _>(size, |buf| black_box(buf)[size - 1]))
+    }
+    #[bench]
+    fn bounded_of_uninit_bytes_unknown(b: &mut Bencher)
+    {
+	let size = *SIZE_RANDOM;
+	b.iter(|| crate::alloca_bounded::<2048, _, _>(size, |buf| black_box(buf).as_ptr()))
+    }
+}
+
+#[cfg(not(feature = "no_std"))]
+#[test]
+fn avec_stack_and_heap()
+{
+    use std::mem::MaybeUninit;
+    use super::AVec;
+
+    let mut buf: Vec<MaybeUninit<String>> = std::iter::repeat_with(MaybeUninit::uninit).take(4).collect();
+    let mut vec = AVec::new(&mut buf[..]);
+    vec.extend(["b", "c", "d"].iter().map(|&s| String::from(s)));
+    vec.insert(0, String::from("a"));
+    assert!(!vec.is_allocated());
+    assert_eq!(&vec[..], &["a", "b", "c", "d"]);
+
+    assert_eq!(vec.remove(1), "b");
+    assert_eq!(vec.swap_remove(0), "a");
+    assert_eq!(vec.pop().as_deref(), Some("c"));
+    assert_eq!(&vec[..], &["d"]);
+
+    vec.extend((0..10).map(|i| i.to_string()));
+    assert!(vec.is_allocated());
+    vec.retain(|s| s.len() == 1 && s != "d");
+    assert_eq!(vec.drain(..5).collect::<Vec<_>>(), ["0", "1", "2", "3", "4"]);
+    vec.truncate(3);
+    assert_eq!(vec.into_iter().collect::<Vec<_>>(), ["5", "6", "7"]);
+}
+
+#[cfg(not(feature = "no_std"))]
+#[test]
+fn avec_drain_stack()
+{
+    use std::mem::MaybeUninit;
+    use super::AVec;
+
+    let mut buf: Vec<MaybeUninit<Box<u32>>> = std::iter::repeat_with(MaybeUninit::uninit).take(10).collect();
+    let mut vec = AVec::new(&mut buf[..]);
+    vec.extend((0..8).map(Box::new));
+
+    let mut drain = vec.drain(2..6);
+    assert_eq!(drain.next().as_deref(), Some(&2));
+    assert_eq!(drain.next_back().as_deref(), Some(&5));
+    drop(drain);
+    assert!(!vec.is_allocated());
+    assert_eq!(vec.iter().map(|x| **x).collect::<Vec<_>>(), [0, 1, 6, 7]);
+
+    vec.clear();
+    assert!(vec.is_empty());
+}
+
+#[cfg(not(feature = "no_std"))]
+#[test]
+fn stackalloc_avec_drops()
+{
+    use std::rc::Rc;
+
+    let counter = Rc::new(());
+    assert_eq!(super::stackalloc_avec(4, |vec| {
+	vec.extend(std::iter::repeat_with(|| counter.clone()).take(3));
+	assert!(!vec.is_allocated());
+	vec.len()
+    }), 3);
+    assert_eq!(Rc::strong_count(&counter), 1);
+
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| super::stackalloc_avec(2, |vec| {
+	vec.extend(std::iter::repeat_with(|| counter.clone()).take(5));
+	assert!(vec.is_allocated());
+	panic!("dropping {} elements", vec.len())
+    })));
+    assert!(result.is_err());
+    assert_eq!(Rc::strong_count(&counter), 1);
+}
+
+#[cfg(all(target_os = "linux", not(feature = "no_std")))]
+#[test]
+fn try_alloca_exhausted()
+{
+    let available = super::stack::available().expect("stack bounds should be known on linux");
+    assert!(available > 0);
+
+    assert_eq!(super::try_alloca_zeroed(1024, |buf| buf.len()), Ok(1024));
+    let err = super::try_alloca(usize::MAX / 2, |_| unreachable!()).unwrap_err();
+    assert_eq!(err.requested, usize::MAX / 2);
+    assert!(err.available < err.requested);
+
+    assert!(matches!(super::try_stackalloc_with_default::<u64, _, _>(usize::MAX / 16, |_| ()), Err(super::TryAllocError::StackExhausted(_))));
+}
+
+#[test]
+fn try_layout_overflow()
+{
+    use super::TryAllocError;
+
+    assert!(matches!(super::try_stackalloc_uninit::<u64, _, _>(usize::MAX / 4, |_| unreachable!()), Err(TryAllocError::Layout(_))));
+    assert!(matches!(super::try_stackalloc_with_default::<u64, _, _>(usize::MAX / 4, |_| unreachable!()), Err(TryAllocError::Layout(_))));
+    assert!(matches!(super::try_alloca_aligned(16, 24, |_| unreachable!()), Err(TryAllocError::Layout(_))));
+    assert_eq!(super::try_stackalloc_uninit::<u64, _, _>(16, |buf| buf.len()), Ok(16));
+
+    #[cfg(not(feature = "no_std"))]
+    {
+	use std::error::Error;
+
+	/// The messages of `err` and every error in its `source()` chain.
+	fn chain(err: &dyn Error) -> Vec<String>
+	{
+	    let mut messages = vec![err.to_string()];
+	    let mut source = err.source();
+	    while let Some(err) = source {
+		messages.push(err.to_string());
+		source = err.source();
+	    }
+	    messages
+	}
+
+	let exhausted = super::StackExhausted { requested: 4096, available: 100 };
+	assert_eq!(chain(&TryAllocError::from(exhausted)), ["cannot allocate on the stack".to_string(), exhausted.to_string()]);
+	let layout = core::alloc::Layout::array::<u64>(usize::MAX).unwrap_err();
+	assert_eq!(chain(&TryAllocError::from(layout.clone())), ["invalid stack allocation size".to_string(), layout.to_string()]);
+    }
+}
+
+#[test]
+#[should_panic(expected = "is too large")]
+fn stackalloc_uninit_overflow()
+{
+    // `size_of::<u64>() * size` wraps around to 8 bytes here.
+    super::stackalloc_uninit::<u64, _, _>(usize::MAX / 8 + 2, |_| ());
+}
+
+#[cfg(not(feature = "no_std"))]
+#[test]
+fn or_heap_threshold()
+{
+    use super::Threshold;
+    use std::rc::Rc;
+
+    let counter = Rc::new(());
+    for &size in &[0, 10, 1000] {
+	let len = super::stackalloc_or_heap(size, Threshold::Fixed(100), counter.clone(), |buf| {
+	    assert_eq!(Rc::strong_count(&counter), size + 1);
+	    buf.len()
+	});
+	assert_eq!(len, size);
+	assert_eq!(Rc::strong_count(&counter), 1);
+    }
+
+    assert!(Threshold::Fixed(100).allows(100));
+    assert!(!Threshold::Fixed(100).allows(101));
+    assert!(!Threshold::Remaining.allows(usize::MAX));
+    assert!(super::alloca_zeroed_or_heap(1 << 24, Threshold::Remaining, |buf| buf.iter().all(|&x| x == 0)));
+}
+
+#[cfg(not(feature = "no_std"))]
+#[test]
+fn stackalloc_str_capacity()
+{
+    use std::fmt::Write;
+
+    super::stackalloc_str(8, |s| {
+	s.push_str("abc");
+	s.push('é');
+	assert_eq!(s, "abcé");
+	assert_eq!(s.try_push_str("dddd"), Err(super::string::CapacityError));
+	assert!(write!(s, "{}", 12345).is_err());
+	assert_eq!(s.pop(), Some('é'));
+	s.push_str("de");
+	assert_eq!(&s[..], "abcde");
+
+	s.set_spill(true);
+	write!(s, "{}", 12345).unwrap();
+	assert!(s.is_allocated());
+	assert_eq!(&s[..], "abcde12345");
+    });
+}
+
+#[cfg(all(unix, not(feature = "no_std")))]
+#[test]
+fn path_cstr()
+{
+    use std::path::Path;
+
+    let path = Path::new("/tmp/some file.txt");
+    assert_eq!(super::with_path_cstr(path, |c| c.to_str().map(String::from)), Ok(Ok(String::from("/tmp/some file.txt"))));
+    assert_eq!(super::with_cstr("", |c| c.to_bytes_with_nul().to_vec()), Ok(vec![0]));
+    assert_eq!(super::with_path_cstr("a\0b", |_| ()).map_err(|e| e.nul_position()), Err(1));
+}
+
+#[test]
+fn tuple_alignment()
+{
+    use std::mem::MaybeUninit;
+
+    #[derive(Default, Clone, PartialEq, Debug)]
+    #[repr(align(64))]
+    struct CacheLine(u8);
+
+    super::stackalloc_tuple((3, 5, 2), |(a, b, c): (&mut [u8], &mut [CacheLine], &mut [String])| {
+	assert_eq!((a.len(), b.len(), c.len()), (3, 5, 2));
+	assert_eq!(b.as_ptr() as usize % 64, 0);
+	assert_eq!(c.as_ptr() as usize % std::mem::align_of::<String>(), 0);
+	assert_eq!(&b[..], &vec![CacheLine::default(); 5][..]);
+	c[1].push_str("dropped");
+    });
+
+    assert_eq!(super::stackalloc_tuple_uninit((10,), |(a,): (&mut [MaybeUninit<u64>],)| a.len()), 10);
+}
+
+#[test]
+fn aligned_exact_padding()
+{
+    for &align in &[1, 2, 16, 64, 4096] {
+	for &size in &[0, 1, 100] {
+	    super::alloca_aligned(size, align, |buf| {
+		assert_eq!(buf.len(), size);
+		assert_eq!(buf.as_ptr() as usize % align, 0);
+	    });
+	}
+    }
+    // Empty slices must still be aligned and in bounds
+    super::stackalloc_uninit(0, |buf: &mut [std::mem::MaybeUninit<u64>]| assert_eq!(buf.as_ptr() as usize % 8, 0));
+}
+
+#[test]
+#[should_panic]
+fn aligned_not_power_of_two()
+{
+    super::alloca_aligned(16, 24, |_| ());
+}
+
+#[cfg(not(feature = "no_std"))]
+#[test]
+fn zst_counts()
+{
+    use std::cell::Cell;
+    use std::mem::MaybeUninit;
+
+    thread_local! {
+	static LIVE: Cell<isize> = const { Cell::new(0) };
+    }
+    struct Marker;
+    impl Default for Marker
+    {
+	fn default() -> Self
+	{
+	    LIVE.with(|live| live.set(live.get() + 1));
+	    Self
+	}
+    }
+    impl Drop for Marker
+    {
+	fn drop(&mut self)
+	{
+	    LIVE.with(|live| live.set(live.get() - 1));
+	}
+    }
+
+    let len = super::stackalloc_with_default(1000, |buf: &mut [Marker]| {
+	assert_eq!(LIVE.with(Cell::get), 1000);
+	buf.len()
+    });
+    assert_eq!(len, 1000);
+    assert_eq!(LIVE.with(Cell::get), 0);
+
+    let len = super::stackalloc_uninit(usize::MAX, |buf: &mut [MaybeUninit<()>]| buf.len());
+    assert_eq!(len, usize::MAX);
+
+    let len = super::stackalloc_with_iter(10, std::iter::repeat_with(Marker::default).take(5), |buf| {
+	assert_eq!(LIVE.with(Cell::get), 5);
+	buf.len()
+    });
+    assert_eq!(len, 5);
+    assert_eq!(LIVE.with(Cell::get), 0);
+
+    super::stackalloc_tuple((3, 0), |(a, b): (&mut [Marker], &mut [u64])| {
+	assert_eq!(a.len(), 3);
+	assert!(b.is_empty());
+	assert_eq!(LIVE.with(Cell::get), 3);
+    });
+    assert_eq!(LIVE.with(Cell::get), 0);
+}
+
+#[cfg(not(feature = "no_std"))]
+#[test]
+fn large_alloca_spans_pages()
+{
+    let sum = std::thread::Builder::new().stack_size(4 * 1024 * 1024).spawn(|| {
+	super::alloca_zeroed(1024 * 1024 + 7, |buf| {
+	    buf.iter_mut().step_by(4096).for_each(|x| *x = 1);
+	    buf.iter().map(|&x| x as usize).sum::<usize>()
+	})
+    }).unwrap().join().unwrap();
+    assert_eq!(sum, (1024usize * 1024 + 7).div_ceil(4096));
+}
+
+#[cfg(not(feature = "no_std"))]
+#[test]
+fn bounded_inline_and_trampoline()
+{
+    use std::rc::Rc;
+
+    let counter = Rc::new(());
+    for &size in &[0, 8, 16, 17, 100] {
+	let len = super::stackalloc_bounded::<16, _, _, _>(size, counter.clone(), |buf| {
+	    assert_eq!(Rc::strong_count(&counter), size + 1);
+	    buf.len()
+	});
+	assert_eq!(len, size);
+	assert_eq!(Rc::strong_count(&counter), 1);
+    }
+
+    assert_eq!(super::alloca_zeroed_bounded::<4, _, _>(3, |buf| buf.to_vec()), [0; 3]);
+    let total = super::stackalloc_with_iter_bounded::<8, _, _, _, _>(8, 1..=10u32, |buf| buf.iter().sum::<u32>());
+    assert_eq!(total, 36);
+}
+
+#[cfg(not(feature = "no_std"))]
+#[test]
+fn stackbox_dyn_drops()
+{
+    use super::{StackBox, stackbox};
+    use std::rc::Rc;
+
+    trait Counted
+    {
+	fn count(&self) -> usize;
+    }
+    impl Counted for Rc<()>
+    {
+	fn count(&self) -> usize
+	{
+	    Rc::strong_count(self)
+	}
+    }
+
+    let counter = Rc::new(());
+    let count = super::stackalloc_box(counter.clone(), |boxed| {
+	let boxed: StackBox<dyn Counted> = stackbox!(boxed => dyn Counted);
+	boxed.count()
+    });
+    assert_eq!(count, 2);
+    assert_eq!(Rc::strong_count(&counter), 1);
+
+    let moved = super::stackalloc_box(counter.clone(), |boxed| {
+	assert_eq!(boxed.count(), 2);
+	StackBox::into_inner(boxed)
+    });
+    assert_eq!(Rc::strong_count(&counter), 2);
+    drop(moved);
+
+    let len = super::stackalloc_box([counter.clone(), counter.clone()], |boxed| {
+	let boxed: StackBox<[Rc<()>]> = stackbox!(boxed => [Rc<()>]);
+	assert_eq!(Rc::strong_count(&counter), 3);
+	boxed.len()
+    });
+    assert_eq!(len, 2);
+    assert_eq!(Rc::strong_count(&counter), 1);
+}
+
+#[cfg(feature = "coerce_unsized")]
+#[test]
+fn stackbox_coerce_unsized()
+{
+    use super::StackBox;
+    use std::fmt::Debug;
+
+    let string = super::stackalloc_box(vec![1, 2, 3], |boxed| {
+	let boxed: StackBox<dyn Debug> = boxed;
+	format!("{:?}", boxed)
+    });
+    assert_eq!(string, "[1, 2, 3]");
+}
+
+#[cfg(not(feature = "no_std"))]
+#[test]
+fn arena_drop_order()
+{
+    use std::cell::RefCell;
+
+    struct Logged<'a>(u32, &'a RefCell<Vec<u32>>);
+    impl<'a> Drop for Logged<'a>
+    {
+	fn drop(&mut self)
+	{
+	    self.1.borrow_mut().push(self.0);
+	}
+    }
+
+    let log = RefCell::new(Vec::new());
+    super::with_stack_arena(256, |arena| {
+	let a = arena.alloc(Logged(1, &log));
+	let s = arena.alloc_str("hello");
+	let b = arena.alloc(Logged(2, &log));
+	let n = arena.alloc_slice_copy(&[1u64, 2, 3]);
+	arena.alloc(Logged(3, &log));
+	assert_eq!((a.0, &*s, b.0, &*n), (1, "hello", 2, &[1, 2, 3][..]));
+	assert!(!arena.is_allocated());
+	assert!(log.borrow().is_empty());
+    });
+    assert_eq!(&log.borrow()[..], &[3, 2, 1]);
+}
+
+#[cfg(not(feature = "no_std"))]
+#[test]
+fn arena_exhausted_and_spill()
+{
+    super::with_stack_arena(64, |arena| {
+	assert!(arena.try_alloc([0u8; 32]).is_ok());
+	assert_eq!(arena.try_alloc([1u8; 64]), Err([1u8; 64]));
+	assert_eq!(arena.try_alloc_str(&"x".repeat(64)), Err(super::ArenaExhausted));
+
+	arena.set_spill(true);
+	let big = arena.alloc(vec![String::from("heap"); 4]);
+	let bytes = arena.alloc_slice_copy(&[7u8; 10000]);
+	assert!(arena.is_allocated());
+	assert_eq!(big.len(), 4);
+	assert!(bytes.iter().all(|&b| b == 7));
+    });
+}
+
+#[cfg(feature = "allocator-api2")]
+#[test]
+fn stack_allocator_api2()
+{
+    use allocator_api2::vec::Vec;
+
+    super::with_stack_allocator(2048, |alloc| {
+	let mut vec = Vec::new_in(alloc);
+	vec.extend(0..16u32);
+	let used = alloc.used();
+	assert!(used >= 64);
+
+	// The vector is the most recent allocation, so it grows in place.
+	vec.reserve_exact(16);
+	assert_eq!(alloc.used() - used, 16 * 4);
+	let other: Vec<u64, _> = Vec::with_capacity_in(100, alloc);
+	assert!(other.capacity() >= 100);
+	drop(other);
+
+	let mut big: Vec<u8, _> = Vec::new_in(alloc);
+	assert!(big.try_reserve(2048).is_err());
+	assert_eq!(vec.iter().sum::<u32>(), 120);
+    });
+}
+
+#[cfg(all(nightly, feature = "allocator_api"))]
+#[test]
+fn stack_allocator_nightly()
+{
+    super::with_stack_allocator(256, |alloc| {
+	let boxed = Box::new_in([1u64; 4], alloc);
+	let mut vec = Vec::with_capacity_in(4, alloc);
+	vec.extend_from_slice(&boxed[..]);
+	assert_eq!(vec.iter().sum::<u64>(), 4);
+	assert!(alloc.used() >= 64);
+    });
+}
+
+#[test]
+fn panic_payload_preserved()
+{
+    let err = std::panic::catch_unwind(|| super::alloca(16, |_| std::panic::panic_any(String::from("payload")))).unwrap_err();
+    assert_eq!(err.downcast_ref::<String>().map(String::as_str), Some("payload"));
+}
+
+#[cfg(all(feature = "stats", not(feature = "no_std")))]
+#[test]
+fn stats_peak_and_reset()
+{
+    use super::stats;
+
+    stats::reset();
+    super::alloca(64, |_| {
+	super::alloca(1000, |_| ());
+	super::alloca(10, |_| ());
+	stats::reset();
+	assert_eq!(stats::snapshot().current_bytes, 64);
+    });
+    let snapshot = stats::snapshot();
+    assert_eq!(snapshot.count, 0);
+    assert_eq!(snapshot.current_bytes, 0);
+    assert_eq!(snapshot.peak_bytes, 64);
+
+    let _ = std::panic::catch_unwind(|| super::alloca(32, |_| panic!()));
+    super::stackalloc_with_default::<u32, _, _>(8, |_| ());
+    let snapshot = stats::snapshot();
+    assert_eq!((snapshot.count, snapshot.current_bytes), (2, 0));
+    // The second allocation is 32 bytes plus alignment padding.
+    assert_eq!(snapshot.histogram[stats::bucket(32)], 2);
+    assert_eq!(stats::bucket(0), 0);
+    assert_eq!(stats::bucket(1), 1);
+    assert_eq!(stats::bucket(32), 6);
+}
+
+#[cfg(not(feature = "no_std"))]
+#[test]
+fn budget_limits_and_falls_back()
+{
+    use super::budget;
+
+    budget::with_budget(1000, || {
+	assert_eq!(budget::available(), Some(1000));
+	super::alloca(600, |_| {
+	    assert_eq!(budget::used(), 600);
+	    let err = super::try_alloca(500, |_| ()).unwrap_err();
+	    assert_eq!(err.available, 400);
+	    assert!(super::try_stackalloc_with_default::<u64, _, _>(100, |_| ()).is_err());
+
+	    // Over budget, so this is on the heap and does not count.
+	    let len = super::alloca(500, |inner| {
+		assert_eq!(budget::used(), 600);
+		inner.len()
+	    });
+	    assert_eq!(len, 500);
+	    budget::with_budget(10_000, || assert_eq!(budget::available(), Some(400)));
+
+	    // The inline array of the bounded functions counts too.
+	    super::stackalloc_uninit_bounded::<16, u64, _, _>(10, |_| assert_eq!(budget::used(), 680));
+	    let used = super::alloca_bounded::<512, _, _>(500, |_| budget::used());
+	    assert_eq!(used, 600);
+	});
+	assert_eq!(budget::used(), 0);
+    });
+    assert_eq!(budget::limit(), None);
+
+    budget::set_thread_limit(100);
+    assert_eq!(super::try_alloca(101, |_| ()).unwrap_err().available, 100);
+    assert_eq!(super::try_alloca(100, |buf| buf.len()), Ok(100));
+    budget::clear_thread_limit();
+    assert_eq!(super::try_alloca(101, |buf| buf.len()), Ok(101));
+}
+
+#[cfg(not(feature = "no_std"))]
+#[test]
+fn secure_wipe_and_drops()
+{
+    use std::mem::MaybeUninit;
+    use std::rc::Rc;
+
+    let mut buf = [MaybeUninit::new(0xaau8); 33];
+    super::secure::wipe(&mut buf[..]);
+    assert!(buf.iter().all(|b| unsafe { b.assume_init() } == 0));
+
+    let counter = Rc::new(());
+    let len = super::stackalloc_secure(5, counter.clone(), |buf| {
+	assert_eq!(Rc::strong_count(&counter), 6);
+	buf.len()
+    });
+    assert_eq!(len, 5);
+    assert_eq!(Rc::strong_count(&counter), 1);
+
+    let result = std::panic::catch_unwind(|| super::alloca_secure(64, |buf| {
+	assert!(buf.iter().all(|&b| b == 0));
+	buf.fill(0xff);
+	panic!("secret")
+    }));
+    assert!(result.is_err());
+
+    // The stack buffers themselves cannot be read after they are deallocated, so check the guard the `secure` functions wrap them in on a local buffer instead.
+    let mut local = [MaybeUninit::new(0u8); 64];
+    let (ptr, len) = super::secure::wiping(&mut local[..], |buf| {
+	buf.fill(MaybeUninit::new(0xff));
+	(buf.as_ptr(), buf.len())
+    });
+    assert_eq!((ptr, len), (local.as_ptr(), 64));
+    assert!(local.iter().all(|b| unsafe { b.assume_init() } == 0));
+
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| super::secure::wiping(&mut local[..], |buf| {
+	buf.fill(MaybeUninit::new(0xff));
+	panic!("secret")
+    })));
+    assert!(result.is_err());
+    assert!(local.iter().all(|b| unsafe { b.assume_init() } == 0));
+}
+
+#[cfg(not(feature = "no_std"))]
+#[test]
+fn panic_safe_partial_drop()
+{
+    use std::cell::Cell;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    thread_local! {
+	static CREATED: Cell<usize> = const { Cell::new(0) };
+	static DROPPED: Cell<usize> = const { Cell::new(0) };
+    }
+    struct Counted;
+    impl Counted
+    {
+	fn new(panic_at: usize) -> Self
+	{
+	    let created = CREATED.with(Cell::get);
+	    if created == panic_at {
+		panic!("init panicked at {}", created);
+	    }
+	    CREATED.with(|c| c.set(created + 1));
+	    Self
+	}
+    }
+    impl Drop for Counted
+    {
+	fn drop(&mut self)
+	{
+	    DROPPED.with(|d| d.set(d.get() + 1));
+	}
+    }
+    fn reset()
+    {
+	CREATED.with(|c| c.set(0));
+	DROPPED.with(|d| d.set(0));
+    }
+    fn counts() -> (usize, usize)
+    {
+	(CREATED.with(Cell::get), DROPPED.with(Cell::get))
+    }
+
+    for &panic_at in &[0, 1, 5, 9] {
+	reset();
+	assert!(catch_unwind(|| super::stackalloc_with(10, || Counted::new(panic_at), |_| ())).is_err());
+	assert_eq!(counts(), (panic_at, panic_at));
+
+	reset();
+	let iter = (0..10).map(|_| Counted::new(panic_at));
+	assert!(catch_unwind(AssertUnwindSafe(|| super::stackalloc_with_iter(10, iter, |_| ()))).is_err());
+	assert_eq!(counts(), (panic_at, panic_at));
+
+	reset();
+	let iter = (0..10).map(|_| Counted::new(panic_at));
+	assert!(catch_unwind(AssertUnwindSafe(|| super::stackalloc_with_iter_or_heap(10, super::Threshold::Fixed(0), iter, |_| ()))).is_err());
+	assert_eq!(counts(), (panic_at, panic_at));
+    }
+
+    reset();
+    assert!(catch_unwind(|| super::stackalloc_with(10, || Counted::new(usize::MAX), |buf| {
+	assert_eq!(buf.len(), 10);
+	panic!("callback panicked")
+    })).is_err());
+    assert_eq!(counts(), (10, 10));
+
+    reset();
+    let iter = (0..20).map(|_| Counted::new(usize::MAX));
+    assert!(catch_unwind(AssertUnwindSafe(|| super::stackalloc_with_iter(5, iter, |_| panic!("callback panicked")))).is_err());
+    assert_eq!(counts(), (5, 5));
+}
+
+#[test]
+fn collect_unknown_length()
+{
+    // `filter()` gives a lower bound of 0, so this needs several chunks.
+    for &len in &[0usize, 1, 63, 64, 65, 1000] {
+	let sum = super::stackalloc_collect((0..len as u32).filter(|_| true), |buf| {
+	    assert_eq!(buf.len(), len);
+	    assert!(buf.iter().enumerate().all(|(i, &x)| x == i as u32));
+	    buf.iter().map(|&x| x as usize).sum::<usize>()
+	});
+	assert_eq!(sum, (0..len).sum::<usize>());
+    }
+    assert_eq!(super::stackalloc_collect((0..1 << 20).map(|_| ()), |buf| buf.len()), 1 << 20);
+}
+
+#[test]
+fn collect_bogus_size_hint()
+{
+    /// Claims to have far more elements than it does.
+    struct Liar<I>(I);
+    impl<I: Iterator> Iterator for Liar<I>
+    {
+	type Item = I::Item;
+	fn next(&mut self) -> Option<Self::Item>
+	{
+	    self.0.next()
+	}
+	fn size_hint(&self) -> (usize, Option<usize>)
+	{
+	    (usize::MAX, None)
+	}
+    }
+
+    assert_eq!(super::stackalloc_collect(Liar(0..10u8), |buf| buf.iter().map(|&x| x as usize).sum::<usize>()), 45);
+    assert_eq!(super::stackalloc_collect(Liar((0..100u64).map(|x| [x; 64])), |buf| {
+	assert!(buf.iter().enumerate().all(|(i, x)| x[63] == i as u64));
+	buf.len()
+    }), 100);
+}
+
+#[cfg(not(feature = "no_std"))]
+#[test]
+fn collect_drops_and_spills()
+{
+    use super::Threshold;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::rc::Rc;
+
+    let counter = Rc::new(());
+    for &threshold in &[Threshold::Fixed(0), Threshold::Fixed(4096), Threshold::Remaining] {
+	let len = super::stackalloc_collect_or_heap((0..500).map(|_| counter.clone()).filter(|_| true), threshold, |buf| {
+	    assert_eq!(Rc::strong_count(&counter), 501);
+	    buf.len()
+	});
+	assert_eq!(len, 500);
+	assert_eq!(Rc::strong_count(&counter), 1);
+    }
+
+    for &panic_at in &[0, 5, 31, 32, 100] {
+	let iter = (0..200).map(|i| {
+	    assert_ne!(i, panic_at);
+	    counter.clone()
+	});
+	assert!(catch_unwind(AssertUnwindSafe(|| super::stackalloc_collect(iter, |_| ()))).is_err());
+	assert_eq!(Rc::strong_count(&counter), 1);
+    }
+
+    assert!(catch_unwind(AssertUnwindSafe(|| super::stackalloc_collect((0..200).map(|_| counter.clone()), |buf| {
+	assert_eq!(buf.len(), 200);
+	panic!("callback panicked")
+    }))).is_err());
+    assert_eq!(Rc::strong_count(&counter), 1);
+}
+
+#[test]
+fn with_iter_rest()
+{
+    let (sum, mut rest) = super::stackalloc_with_iter_rest(10, 0..25u32, |buf| {
+	assert_eq!(buf, &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+	buf.iter().sum::<u32>()
+    });
+    assert_eq!(sum, 45);
+    assert_eq!(rest.next(), Some(10));
+
+    let mut lens = [0; 4];
+    let mut iter = 0..25u32;
+    for len in lens.iter_mut() {
+	let (n, more) = super::stackalloc_with_iter_rest(10, iter, |buf| buf.len());
+	*len = n;
+	iter = more;
+    }
+    assert_eq!(lens, [10, 10, 5, 0]);
+}
+
+#[cfg(not(feature = "no_std"))]
+#[test]
+fn chunks_reuse_and_drop()
+{
+    use core::ops::ControlFlow;
+    use std::rc::Rc;
+
+    let counter = Rc::new(());
+    let mut lens = Vec::new();
+    let flow = super::chunks((0..23).map(|_| counter.clone()), 5, |batch| {
+	// The previous batch has been dropped.
+	assert_eq!(Rc::strong_count(&counter), batch.len() + 1);
+	lens.push(batch.len());
+	ControlFlow::<()>::Continue(())
+    });
+    assert_eq!(flow, ControlFlow::Continue(()));
+    assert_eq!(lens, [5, 5, 5, 5, 3]);
+    assert_eq!(Rc::strong_count(&counter), 1);
+
+    let mut iter = 0..100;
+    let mut calls = 0;
+    let flow = super::chunks(iter.by_ref(), 10, |batch| {
+	calls += 1;
+	match batch.iter().position(|&x| x == 42) {
+	    Some(i) => ControlFlow::Break(batch[i]),
+	    None => ControlFlow::Continue(()),
+	}
+    });
+    assert_eq!(flow, ControlFlow::Break(42));
+    assert_eq!(calls, 5);
+    assert_eq!(iter.next(), Some(50));
+
+    assert_eq!(super::chunks(0..0, 4, |_: &mut [i32]| -> ControlFlow<()> { unreachable!() }), ControlFlow::Continue(()));
+}
+
+#[cfg(not(feature = "no_std"))]
+#[test]
+fn read_chunks_interrupted()
+{
+    use core::ops::ControlFlow;
+    use std::io;
+
+    /// Reads at most 7 bytes at a time, interrupting every other read.
+    struct Slow<'a>(&'a [u8], bool);
+    impl io::Read for Slow<'_>
+    {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>
+	{
+	    self.1 = !self.1;
+	    if self.1 {
+		return Err(io::ErrorKind::Interrupted.into());
+	    }
+	    let len = buf.len().min(7);
+	    io::Read::read(&mut self.0, &mut buf[..len])
+	}
+    }
+
+    let data: Vec<u8> = (0..100).collect();
+    let mut out = Vec::new();
+    let flow = super::read_chunks(Slow(&data, false), 16, |bytes| {
+	assert!(bytes.len() <= 7);
+	out.extend_from_slice(bytes);
+	ControlFlow::<()>::Continue(())
+    }).unwrap();
+    assert_eq!(flow, ControlFlow::Continue(()));
+    assert_eq!(out, data);
+
+    let flow = super::read_chunks(&data[..], 16, |bytes| match bytes[0] {
+	32 => ControlFlow::Break(bytes.len()),
+	_ => ControlFlow::Continue(()),
+    }).unwrap();
+    assert_eq!(flow, ControlFlow::Break(16));
+}
+
+#[cfg(not(feature = "no_std"))]
+#[test]
+fn io_copy_and_bufreader()
+{
+    use super::io::*;
+    use std::io::{BufRead, Read};
+
+    let data: Vec<u8> = (0..=255).cycle().take(1000).collect();
+    let mut out = Vec::new();
+    assert_eq!(copy_with_stack_buffer(&mut &data[..], &mut out, 64).unwrap(), 1000);
+    assert_eq!(out, data);
+
+    let mut reader = &data[..];
+    assert_eq!(read_exact_into(&mut reader, 10, |buf| buf[9]).unwrap(), 9);
+    assert!(read_exact_into(&mut reader, 1000, |_| unreachable!()).is_err());
+
+    let text = "a\nbb\n\nccc";
+    let lines = with_stack_bufreader(text.as_bytes(), 3, |reader| {
+	assert_eq!(reader.capacity(), 3);
+	reader.lines().collect::<Result<Vec<_>, _>>().unwrap()
+    });
+    assert_eq!(lines, ["a", "bb", "", "ccc"]);
+
+    let mut reader = &data[..];
+    with_stack_bufreader(&mut reader, 16, |reader| {
+	let mut small = [0u8; 4];
+	reader.read_exact(&mut small).unwrap();
+	assert_eq!(small, [0, 1, 2, 3]);
+	assert_eq!(reader.buffer(), &data[4..16]);
+	let mut large = [0u8; 32];
+	reader.read_exact(&mut large).unwrap();
+	assert_eq!(&large[..], &data[4..36]);
+    });
+    // The rest of the large read skipped the buffer.
+    assert_eq!(reader.len(), 1000 - 36);
+}
+
+#[cfg(not(feature = "no_std"))]
+#[test]
+fn io_bufwriter_flushes_and_errors()
+{
+    use super::io::*;
+    use std::io::{self, Write};
+
+    /// Accepts at most `limit` bytes, at most 5 bytes per write.
+    struct Limited
+    {
+	data: Vec<u8>,
+	limit: usize,
+	writes: usize,
+    }
+    impl Write for Limited
+    {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize>
+	{
+	    self.writes += 1;
+	    let len = buf.len().min(5).min(self.limit - self.data.len());
+	    if len == 0 && !buf.is_empty() {
+		return Err(io::Error::other("full"));
+	    }
+	    self.data.extend_from_slice(&buf[..len]);
+	    Ok(len)
+	}
+	fn flush(&mut self) -> io::Result<()>
+	{
+	    Ok(())
+	}
+    }
+
+    let mut out = Limited { data: Vec::new(), limit: 100, writes: 0 };
+    let len = with_stack_bufwriter(&mut out, 16, |writer| {
+	for i in 0..10 {
+	    write!(writer, "{}", i)?;
+	}
+	assert!(writer.get_ref().data.is_empty());
+	assert_eq!(writer.buffer(), b"0123456789");
+	writer.write_all(b"abcdefghijklmnopqrstuvwxyz")?;
+	Ok(writer.buffer().len())
+    }).unwrap();
+    // The underlying writer takes 5 bytes at a time, so the tail of the large write is buffered.
+    assert_eq!(len, 11);
+    assert_eq!(out.data, b"0123456789abcdefghijklmnopqrstuvwxyz");
+
+    // Flushing on exit surfaces errors.
+    let mut out = Limited { data: Vec::new(), limit: 12, writes: 0 };
+    let err = with_stack_bufwriter(&mut out, 64, |writer| writer.write_all(b"hello, world!")).unwrap_err();
+    assert_eq!(err.to_string(), "full");
+    assert_eq!(out.data, b"hello, world");
+
+    // Errors from the callback take precedence, but the buffer is still flushed.
+    let mut out = Limited { data: Vec::new(), limit: 100, writes: 0 };
+    let err = with_stack_bufwriter(&mut out, 64, |writer| -> io::Result<()> {
+	writer.write_all(b"partial")?;
+	Err(io::Error::other("callback"))
+    }).unwrap_err();
+    assert_eq!(err.to_string(), "callback");
+    assert_eq!(out.data, b"partial");
+    assert_eq!(out.writes, 2);
 }
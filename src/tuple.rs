@@ -0,0 +1,159 @@
+//! Allocating multiple slices of different types with a single stack allocation.
+//!
+//! The element types are inferred from the callback's parameter, so it must be annotated.
+//! # Example
+//! ```
+//! # use stackalloc::stackalloc_tuple;
+//! let total = stackalloc_tuple((4, 2, 8), |(a, b, c): (&mut [u8], &mut [u32], &mut [f64])| {
+//!  a.fill(1);
+//!  b.fill(10);
+//!  c.fill(0.5);
+//!  a.iter().map(|&x| x as f64).sum::<f64>() + b.iter().map(|&x| x as f64).sum::<f64>() + c.iter().sum::<f64>()
+//! });
+//! assert_eq!(total, 28.0);
+//! ```
+use super::*;
+
+/// A tuple of element types of the slices allocated by `stackalloc_tuple()` and `stackalloc_tuple_uninit()`.
+///
+/// This is implemented for tuples of up to 8 types.
+pub trait SliceTuple: Sized
+{
+    /// A tuple of the number of elements in each slice.
+    type Lens: Copy;
+
+    /// The offset of each slice from the start of the buffer, the total size of the buffer, and the alignment of the buffer needed to hold the slices.
+    ///
+    /// # Panics
+    /// If the total size overflows.
+    #[doc(hidden)] fn layout(lens: Self::Lens) -> (Self::Lens, usize, usize);
+}
+
+/// A callback taking a tuple of mutable slices of `T`'s element types.
+///
+/// This is implemented for all closures taking an appropriate tuple of slices of types that implement `Default`.
+pub trait TupleFn<T: SliceTuple, U>
+{
+    /// Fill the slices in the buffer at `base` by calling `Default::default()`, call `self` with them, and then drop their elements.
+    ///
+    /// # Safety
+    /// `base` must be aligned and valid for the size returned by `T::layout(lens)`, with `offsets` also returned by it.
+    #[doc(hidden)] unsafe fn call_in(self, base: *mut u8, offsets: T::Lens, lens: T::Lens) -> U;
+}
+
+/// A callback taking a tuple of mutable slices of `T`'s element types wrapped in `MaybeUninit`.
+///
+/// This is implemented for all closures taking an appropriate tuple of slices.
+pub trait TupleUninitFn<T: SliceTuple, U>
+{
+    /// Call `self` with the slices in the buffer at `base`.
+    ///
+    /// # Safety
+    /// `base` must be aligned and valid for the size returned by `T::layout(lens)`, with `offsets` also returned by it.
+    #[doc(hidden)] unsafe fn call_in(self, base: *mut u8, offsets: T::Lens, lens: T::Lens) -> U;
+}
+
+/// Nest calls to `init_with_in()` for each uninitialised slice, and call `callback` with all of them once initialised.
+macro_rules! init_nested {
+    ($callback:ident ($($done:ident)*)) => {
+	$callback(($($done,)*))
+    };
+    ($callback:ident ($($done:ident)*) $slice:ident $($rest:ident)*) => {
+	init_with_in($slice, Default::default, move |$slice| init_nested!($callback ($($done)* $slice) $($rest)*))
+    };
+}
+
+macro_rules! slice_tuple {
+    (@usize $T:ident) => (usize);
+    ($($T:ident $len:ident $offset:ident),+) => {
+	impl<$($T),+> SliceTuple for ($($T,)+)
+	{
+	    type Lens = ($(slice_tuple!(@usize $T),)+);
+
+	    fn layout(lens: Self::Lens) -> (Self::Lens, usize, usize)
+	    {
+		let ($($len,)+) = lens;
+		let layout = Layout::from_size_align(0, 1).unwrap();
+		$(
+		    let (layout, $offset) = layout.extend(array_layout::<$T>($len)).expect("stackalloc_tuple: buffer is too large");
+		)+
+		(($($offset,)+), layout.size(), layout.align())
+	    }
+	}
+
+	impl<$($T: Default,)+ U, F> TupleFn<($($T,)+), U> for F
+	where F: for<'x> FnOnce(($(&'x mut [$T],)+)) -> U
+	{
+	    #[inline(always)] unsafe fn call_in(self, base: *mut u8, offsets: ($(slice_tuple!(@usize $T),)+), lens: ($(slice_tuple!(@usize $T),)+)) -> U
+	    {
+		let ($($len,)+) = lens;
+		let ($($offset,)+) = offsets;
+		$(
+		    let $len = slice::from_raw_parts_mut(base.add($offset) as *mut MaybeUninit<$T>, $len);
+		)+
+		init_nested!(self () $($len)+)
+	    }
+	}
+
+	impl<$($T,)+ U, F> TupleUninitFn<($($T,)+), U> for F
+	where F: for<'x> FnOnce(($(&'x mut [MaybeUninit<$T>],)+)) -> U
+	{
+	    #[inline(always)] unsafe fn call_in(self, base: *mut u8, offsets: ($(slice_tuple!(@usize $T),)+), lens: ($(slice_tuple!(@usize $T),)+)) -> U
+	    {
+		let ($($len,)+) = lens;
+		let ($($offset,)+) = offsets;
+		self(($(slice::from_raw_parts_mut(base.add($offset) as *mut MaybeUninit<$T>, $len),)+))
+	    }
+	}
+    };
+}
+
+slice_tuple!(A a_len a_off);
+slice_tuple!(A a_len a_off, B b_len b_off);
+slice_tuple!(A a_len a_off, B b_len b_off, C c_len c_off);
+slice_tuple!(A a_len a_off, B b_len b_off, C c_len c_off, D d_len d_off);
+slice_tuple!(A a_len a_off, B b_len b_off, C c_len c_off, D d_len d_off, E e_len e_off);
+slice_tuple!(A a_len a_off, B b_len b_off, C c_len c_off, D d_len d_off, E e_len e_off, G g_len g_off);
+slice_tuple!(A a_len a_off, B b_len b_off, C c_len c_off, D d_len d_off, E e_len e_off, G g_len g_off, H h_len h_off);
+slice_tuple!(A a_len a_off, B b_len b_off, C c_len c_off, D d_len d_off, E e_len e_off, G g_len g_off, H h_len h_off, J j_len j_off);
+
+/// Allocate the buffer for a `SliceTuple` in one stack allocation and call `callback` with its aligned base pointer and the slice offsets.
+#[inline(always)] fn alloca_tuple<T, U, F>(lens: T::Lens, callback: F) -> U
+where T: SliceTuple,
+      F: FnOnce(*mut u8, T::Lens) -> U
+{
+    let (offsets, size, align) = T::layout(lens);
+    if size == 0 {
+	// Every slice is empty or zero-sized, so they only need a non-null pointer aligned to `align`.
+	return callback(align as *mut u8, offsets);
+    }
+    alloca_aligned(size, align, move |buf| callback(buf.as_mut_ptr() as *mut u8, offsets))
+}
+
+/// Allocate runtime length slices of uninitialised element types `T` in a single stack allocation, call `callback` with these buffers, and then deallocate them.
+///
+/// `lens` is a tuple of the number of elements in each slice. Each slice is aligned to its element type.
+/// The element types are inferred from the parameter of `callback`, which must be a tuple of `&mut [MaybeUninit<_>]` of the same arity as `lens`.
+///
+/// See `stackalloc_uninit()`.
+#[inline] pub fn stackalloc_tuple_uninit<T, U, F>(lens: T::Lens, callback: F) -> U
+where T: SliceTuple,
+      F: TupleUninitFn<T, U>
+{
+    // SAFETY: `base` is aligned to and valid for the layout of `T`.
+    alloca_tuple::<T, _, _>(lens, move |base, offsets| unsafe { callback.call_in(base, offsets, lens) })
+}
+
+/// Allocate runtime length slices of element types `T` in a single stack allocation, fill them by calling `Default::default()`, call `callback` with these buffers, and then drop and deallocate them.
+///
+/// `lens` is a tuple of the number of elements in each slice. Each slice is aligned to its element type.
+/// The element types are inferred from the parameter of `callback`, which must be a tuple of `&mut [_]` of the same arity as `lens`.
+///
+/// This is a lot cheaper than nesting calls to `stackalloc_with_default()`, since only one trip through the FFI trampoline is needed.
+#[inline] pub fn stackalloc_tuple<T, U, F>(lens: T::Lens, callback: F) -> U
+where T: SliceTuple,
+      F: TupleFn<T, U>
+{
+    // SAFETY: `base` is aligned to and valid for the layout of `T`.
+    alloca_tuple::<T, _, _>(lens, move |base, offsets| unsafe { callback.call_in(base, offsets, lens) })
+}